@@ -1,15 +1,21 @@
 // ui.rs
 use crate::{
-    gif,
-    message::{Disconnect, Connect, MessageType, Notification},
+    attachment::{AttachmentDescriptor, PendingFile, Preparing, state::Attachments, transfer::Transfers},
+    emoji::{EmojiPicker, RecentEmoji, ServerEmoji},
+    gif::{self, provider::GifProvider},
+    hash::content_hash,
+    link_preview::{LinkPreviews, PreviewMode},
+    mention::MentionInbox,
+    message::{Avatar, Disconnect, Connect, MessageType, Notification, StatusUpdate},
     network::{client::NetworkClient, state::NetworkState},
-    tenor,
-    user::state::UserState,
+    sticker::Stickers,
+    ui::{lightbox::Lightbox, texture_cache::TextureCache},
+    user::{presence::Presence, state::{ProfilePicture, UserState}},
     views::state::View,
 };
 use egui_file_dialog::FileDialog;
 use local_ip_address::local_ip;
-use std::{collections::HashMap, net::SocketAddr};
+use std::{net::SocketAddr, path::PathBuf, sync::Arc};
 use tokio::sync::mpsc::channel;
 
 pub struct App {
@@ -19,18 +25,50 @@ pub struct App {
     pub(crate) env: Env,
     pub(crate) view: View,
     pub(crate) rt_handle: tokio::runtime::Handle,
-    pub(crate) tenor_api: tenor::TenorAPI,
-    pub(crate) gif_cache: HashMap<String, gif::Gif>,
+    // None when neither a tenor key nor a gif folder is set up
+    pub(crate) gif_provider: Option<Arc<dyn GifProvider>>,
+    pub(crate) gifs: gif::GifFeed,
+    pub(crate) gif_media: gif::media::GifMedia,
+    pub(crate) saved_gifs: gif::saved::SavedGifs,
+    pub(crate) recent_emoji: RecentEmoji,
+    // custom emoji of the server we're connected to
+    pub(crate) server_emoji: ServerEmoji,
+    pub(crate) stickers: Stickers,
+    pub(crate) link_previews: LinkPreviews,
+    pub(crate) attachments: Attachments,
+    pub(crate) textures: TextureCache,
+    pub(crate) transfers: Transfers,
+    pub(crate) settings: Settings,
 }
 
 // handles the input/output of app
 #[derive(Default)]
 pub(crate) struct Io {
     pub(crate) file_dialog: FileDialog,
+    pub(crate) save_dialog: FileDialog,
+    // picks the image for a new custom emoji
+    pub(crate) emoji_dialog: FileDialog,
     pub(crate) gif_search_text: String,
-    pub(crate) image_bytes: Vec<u8>,
+    pub(crate) gif_browse: gif::GifBrowse,
+    // index of the pack open in the sticker popup
+    pub(crate) sticker_pack: usize,
+    pub(crate) emoji_picker: EmojiPicker,
+    // files waiting in the staging strip to go out with the next message
+    pub(crate) staged: Vec<PendingFile>,
+    pub(crate) preparing: Preparing,
+    pub(crate) attach_error: Option<String>,
+    // the attachment the save dialog is open for
+    pub(crate) saving: Option<AttachmentDescriptor>,
+    // the image open in the lightbox
+    pub(crate) lightbox: Option<Lightbox>,
+    // saves waiting for their attachment to finish downloading
+    pub(crate) pending_saves: Vec<(PathBuf, AttachmentDescriptor)>,
     pub(crate) message_text: String,
+    pub(crate) focus_composer: bool,
+    // a paste shortcut press was seen, so its release doesn't paste again
+    pub(crate) paste_pressed: bool,
     pub(crate) messages: Vec<MessageType>,
+    pub(crate) mentions: MentionInbox,
 }
 
 #[derive(Default)]
@@ -38,91 +76,186 @@ pub(crate) struct Env {
     pub(crate) window_size: egui::Vec2,
 }
 
+// user preferences
+pub(crate) struct Settings {
+    pub(crate) link_previews: PreviewMode,
+    pub(crate) recompress_images: bool,
+    // send gifs as links recipients download themselves, rather than as files
+    pub(crate) gifs_by_reference: bool,
+}
+
+// $XDG_DATA_HOME/rust-chat, ~/.local/share/rust-chat or the working directory
+pub(crate) fn data_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_default();
+    base.join("rust-chat")
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            link_previews: PreviewMode::default(),
+            recompress_images: true,
+            gifs_by_reference: true,
+        }
+    }
+}
+
 impl App {
     pub fn new(handle: tokio::runtime::Handle) -> Self {
-        let (tx, rx) = channel::<Vec<gif::Gif>>(128);
+        let (tx, rx) = channel::<gif::GifEvent>(128);
         let sock = SocketAddr::new(local_ip().unwrap(), 5000);
-        let tenor = match tenor::TenorAPI::new() {
-            Ok(api) => api,
-            Err(_) => panic!(),
-        };
-        let paths = vec![
-            "file://assets/2000c.png".to_string(),
-            "file://assets/20002.png".to_string(),
-            "file://assets/20003.png".to_string(),
-            "file://assets/20006.png".to_string(),
-            "file://assets/20007.png".to_string(),
-            "file://assets/20008.png".to_string(),
-            "file://assets/21019.png".to_string(),
-            "file://assets/21042.png".to_string(),
-        ];
+        let pictures = [
+            "assets/2000c.png",
+            "assets/20002.png",
+            "assets/20003.png",
+            "assets/20006.png",
+            "assets/20007.png",
+            "assets/20008.png",
+            "assets/21019.png",
+            "assets/21042.png",
+        ]
+        .into_iter()
+        .filter_map(|path| match std::fs::read(path) {
+            Ok(bytes) => Some(ProfilePicture { hash: content_hash(&bytes), bytes: bytes.into() }),
+            Err(e) => {
+                eprintln!("could not load profile picture {path}: {e}");
+                None
+            }
+        })
+        .collect();
 
         let net = NetworkState {
-            tx: tx,
-            rx: rx,
+            tx,
+            rx,
             socket_addr: sock,
             ip_str: sock.to_string(),
             bad_ip_msg: false,
             client: None,
         };
 
-        let mut user = UserState::default();
-        user.profile_picture_list = paths;
-        let io = Io::default();
+        let user = UserState {
+            profile_picture_list: pictures,
+            ..Default::default()
+        };
+        let mut attachments = Attachments::default();
+        let stickers = Stickers::from_env(&mut attachments);
+        let io = Io {
+            save_dialog: FileDialog::new().id("save_dialog").title("Save attachment"),
+            emoji_dialog: FileDialog::new().id("emoji_dialog").title("Add a custom emoji"),
+            ..Default::default()
+        };
 
         Self {
             network: net,
-            user: user,
-            io: io,
+            user,
+            io,
             env: Env::default(),
             view: View::Start,
             rt_handle: handle,
-            tenor_api: tenor,
-            gif_cache: HashMap::new(),
+            gif_provider: gif::provider::from_env(),
+            gifs: gif::GifFeed::default(),
+            gif_media: gif::media::GifMedia::default(),
+            saved_gifs: gif::saved::SavedGifs::load(gif::saved::default_dir()),
+            recent_emoji: RecentEmoji::load(data_dir().join("recent_emoji.json")),
+            server_emoji: ServerEmoji::default(),
+            link_previews: LinkPreviews::default(),
+            attachments,
+            stickers,
+            textures: TextureCache::default(),
+            transfers: Transfers::default(),
+            settings: Settings::default(),
         }
     }
 
-    fn handle_connect(&mut self) {
+    fn handle_connect(&mut self, ctx: &egui::Context) {
         self.network.client = Some(NetworkClient::connect(
             self.network.socket_addr,
             &self.rt_handle,
+            ctx.clone(),
         ));
         self.view = View::Chat;
 
-        // messages to send
-        let messages: Vec<MessageType> = vec![
-            MessageType::Connect(Connect {
-                user_name: self.user.local.name.clone(),
-            }),
-            MessageType::Notification(Notification {
+        // messages to send, invisible users join without telling anyone
+        let mut messages: Vec<MessageType> = vec![MessageType::Connect(Connect {
+            user_name: self.user.local.name.clone(),
+            status: Some(self.user.local.status.clone()),
+            // set to the server's token to connect as an admin
+            admin_token: std::env::var("CHAT_ADMIN_TOKEN").ok().filter(|token| !token.is_empty()),
+        })];
+        if self.user.local.status.presence != Presence::Invisible {
+            messages.push(MessageType::Notification(Notification {
                 message: format!("{} has joined the chat", self.user.local.name),
-            }),
-        ];
+            }));
+        }
+
+        // the avatar goes out once, messages only carry its hash
+        if !self.user.local.picture.is_empty() {
+            self.user.avatars.insert(
+                self.user.local.picture_hash.clone(),
+                self.user.local.picture.clone().into(),
+            );
+            messages.insert(1, MessageType::Avatar(Avatar {
+                hash: self.user.local.picture_hash.clone(),
+                bytes: self.user.local.picture.clone(),
+            }));
+        }
 
         // send messages
         if let Some(net) = &self.network.client {
             for message in messages {
-                net.send(message.clone(), &self.rt_handle);
+                net.send(message.clone());
             }
         }
     }
 
+    // connects again after the connection dropped, unfinished transfers
+    // carry on where they stopped
+    pub(crate) fn reconnect(&mut self, ctx: &egui::Context) {
+        self.handle_connect(ctx);
+        if let Some(net) = &self.network.client {
+            self.transfers.resume(net);
+            self.attachments.resume(net);
+        }
+    }
+
+    // shows a line of information in the chat, visible only to this user
+    pub(crate) fn notify(&mut self, message: impl Into<String>) {
+        self.io.messages.push(MessageType::Notification(Notification {
+            message: message.into(),
+        }));
+    }
+
+    // tells the server about a change to the local user's status
+    pub(crate) fn send_status(&self) {
+        let message = MessageType::Status(StatusUpdate {
+            user_name: self.user.local.name.clone(),
+            status: self.user.local.status.clone(),
+        });
+
+        if let Some(net) = &self.network.client {
+            net.send(message);
+        }
+    }
+
     fn handle_disconnect(&mut self) {
-        // messages to send
-        let messages: Vec<MessageType> = vec![
-            MessageType::Disconnect(Disconnect {
-                user_name: self.user.local.name.clone(),
-                ip: self.network.ip_str.clone(),
-            }),
-            MessageType::Notification(Notification {
+        // messages to send, invisible users leave as quietly as they came
+        let mut messages: Vec<MessageType> = vec![MessageType::Disconnect(Disconnect {
+            user_name: self.user.local.name.clone(),
+            ip: self.network.ip_str.clone(),
+        })];
+        if self.user.local.status.presence != Presence::Invisible {
+            messages.push(MessageType::Notification(Notification {
                 message: format!("{} has left the chat", self.user.local.name),
-            }),
-        ];
+            }));
+        }
 
         // send messages
         if let Some(net) = &self.network.client {
             for message in messages {
-                net.send(message, &self.rt_handle);
+                net.send(message);
             }
         }
     }
@@ -136,7 +269,7 @@ impl eframe::App for App {
         match self.view {
             View::Start => self.render_start(ctx),
             View::Chat => self.render_chat(ctx),
-            View::Connect => self.handle_connect(),
+            View::Connect => self.handle_connect(ctx),
             View::Select => self.render_select(ctx),
         }
     }
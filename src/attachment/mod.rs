@@ -0,0 +1,246 @@
+// attachments travel separately from messages, in chunks, and are stored by
+// the server keyed by the SHA-256 of their content
+pub mod state;
+pub mod store;
+pub mod thumbnail;
+pub mod transfer;
+pub mod validate;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use crate::hash::content_hash;
+
+pub const CHUNK_SIZE: usize = 64 * 1024;
+// largest attachment the server accepts
+pub const MAX_ATTACHMENT_BYTES: u64 = 25 * 1024 * 1024;
+// attachments that can be sent with one message
+pub const MAX_STAGED_FILES: usize = 10;
+// downloads at least this big get a progress bar
+pub const LARGE_TRANSFER_BYTES: u64 = 512 * 1024;
+
+// what a message carries about an attachment, the bytes are fetched lazily
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AttachmentDescriptor {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    pub mime: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    // hash of a small preview shown in place of a large image
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub caption: String,
+}
+
+impl AttachmentDescriptor {
+    // works out the type and, for images, the dimensions of a file. images
+    // that fail validation are described as plain files
+    pub fn describe(name: &str, bytes: &[u8]) -> Self {
+        let info = validate::check_image(bytes).ok();
+        let format = info.as_ref().map(|i| i.format);
+        let dimensions = info.as_ref().map(|i| (i.width, i.height));
+
+        Self {
+            hash: content_hash(bytes),
+            name: name.to_string(),
+            size: bytes.len() as u64,
+            mime: format
+                .map(|f| f.to_mime_type().to_string())
+                .unwrap_or_else(|| mime_from_name(name).to_string()),
+            width: dimensions.map(|d| d.0),
+            height: dimensions.map(|d| d.1),
+            thumbnail: None,
+            caption: String::new(),
+        }
+    }
+
+    // the blobs a message with this attachment depends on
+    pub fn blob_hashes(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.hash.as_str()).chain(self.thumbnail.as_deref())
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.mime.starts_with("image/")
+    }
+
+    // icon shown on file cards
+    pub fn icon(&self) -> &'static str {
+        match self.mime.as_str() {
+            "application/pdf" => "📕",
+            "application/zip" | "application/gzip" | "application/x-tar" | "application/x-7z-compressed" => "📦",
+            m if m.starts_with("text/") || m == "application/json" => "📝",
+            m if m.starts_with("audio/") => "🎵",
+            m if m.starts_with("video/") => "🎞",
+            m if m.starts_with("image/") => "🖼",
+            _ => "📄",
+        }
+    }
+}
+
+// mime type for files the image sniffing doesn't recognise
+fn mime_from_name(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("gz" | "tgz") => "application/gzip",
+        Some("tar") => "application/x-tar",
+        Some("7z") => "application/x-7z-compressed",
+        Some("txt" | "log") => "text/plain",
+        Some("md") => "text/markdown",
+        Some("csv") => "text/csv",
+        Some("rs" | "toml" | "py" | "js" | "ts" | "c" | "h" | "cpp" | "sh") => "text/plain",
+        Some("json") => "application/json",
+        Some("html" | "htm") => "text/html",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+// human readable file size
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+// a file staged in the composer that hasn't been sent yet
+pub struct PendingFile {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub thumbnail: Option<Vec<u8>>,
+    pub caption: String,
+    // hash of the image shown for it in the staging strip, None for other files
+    pub preview: Option<String>,
+}
+
+impl PendingFile {
+    pub fn new(name: String, bytes: Vec<u8>) -> Self {
+        let format = validate::sniff(&bytes);
+        let bytes = match format {
+            Some(image::ImageFormat::Jpeg) => validate::strip_jpeg_metadata(&bytes).unwrap_or(bytes),
+            _ => bytes,
+        };
+        let preview = format.is_some().then(|| content_hash(&bytes));
+        Self { name, bytes, thumbnail: None, caption: String::new(), preview }
+    }
+
+    // still images get a thumbnail and, if `recompress` is set, are shrunk
+    pub fn prepare(name: String, bytes: Vec<u8>, recompress: bool) -> Self {
+        match thumbnail::prepare(&name, &bytes, recompress) {
+            Some(image) => Self {
+                preview: Some(content_hash(image.thumbnail.as_ref().unwrap_or(&image.bytes))),
+                name: image.name,
+                bytes: image.bytes,
+                thumbnail: image.thumbnail,
+                caption: String::new(),
+            },
+            None => Self::new(name, bytes),
+        }
+    }
+
+    // the bytes behind `preview`
+    pub fn preview_bytes(&self) -> &[u8] {
+        self.thumbnail.as_deref().unwrap_or(&self.bytes)
+    }
+}
+
+// files being decoded, shrunk and thumbnailed on a blocking thread, so a big
+// photo doesn't freeze the UI. they are staged once they are ready
+pub struct Preparing {
+    tx: UnboundedSender<PendingFile>,
+    rx: UnboundedReceiver<PendingFile>,
+    // files still being worked on
+    pub count: usize,
+}
+
+impl Default for Preparing {
+    fn default() -> Self {
+        let (tx, rx) = unbounded_channel();
+        Self { tx, rx, count: 0 }
+    }
+}
+
+impl Preparing {
+    pub fn start(&mut self, rt: &tokio::runtime::Handle, name: String, bytes: Vec<u8>, recompress: bool) {
+        let tx = self.tx.clone();
+        self.count += 1;
+        rt.spawn_blocking(move || {
+            let _ = tx.send(PendingFile::prepare(name, bytes, recompress));
+        });
+    }
+
+    // a file that has finished preparing
+    pub fn next(&mut self) -> Option<PendingFile> {
+        let file = self.rx.try_recv().ok()?;
+        self.count -= 1;
+        Some(file)
+    }
+}
+
+// a piece of a blob, used both for uploads and downloads
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlobChunk {
+    pub hash: String,
+    pub offset: u64,
+    pub total: u64,
+    pub data: Vec<u8>,
+    // the fetch a downloaded chunk answers, 0 for uploads
+    #[serde(default)]
+    pub fetch: u64,
+}
+
+// splits a blob into the chunks that upload it
+pub fn chunks(hash: &str, bytes: &[u8]) -> Vec<BlobChunk> {
+    bytes
+        .chunks(CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, data)| BlobChunk {
+            hash: hash.to_string(),
+            offset: (i * CHUNK_SIZE) as u64,
+            total: bytes.len() as u64,
+            data: data.to_vec(),
+            fetch: 0,
+        })
+        .collect()
+}
+
+// asks for a blob, starting at a chunk boundary so downloads can resume
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchBlob {
+    pub hash: String,
+    pub offset: u64,
+    // picked by the client and sent back with every chunk, so chunks still
+    // on their way from an earlier fetch of the same blob can be told apart
+    #[serde(default)]
+    pub id: u64,
+}
+
+// how much of an upload the server has, sent after every chunk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadAck {
+    pub hash: String,
+    pub received: u64,
+}
+
+// sent instead of an ack when the server won't take an upload
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadRejected {
+    pub hash: String,
+    pub reason: String,
+}
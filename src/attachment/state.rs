@@ -0,0 +1,332 @@
+// client side cache of attachment blobs, fetched from the server on demand
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    attachment::{AttachmentDescriptor, BlobChunk, FetchBlob, format_size},
+    hash::content_hash,
+    message::MessageType,
+    network::client::NetworkClient,
+    ui::texture_cache::TextureCache,
+};
+
+// how long to wait before asking again for a blob the server didn't have
+const RETRY_AFTER_SECS: f64 = 2.0;
+
+pub(crate) struct Download {
+    pub name: String,
+    pub size: u64,
+    buf: Vec<u8>,
+    // id of the fetch, chunks from any other fetch are stale
+    fetch: u64,
+}
+
+impl Download {
+    pub fn received(&self) -> u64 {
+        self.buf.len() as u64
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.buf.len() as f32 / self.size.max(1) as f32
+    }
+}
+
+enum BlobState {
+    Downloading(Download),
+    Ready(Arc<[u8]>),
+    // the server didn't have it (yet), holds the time of the answer
+    Missing(f64),
+    // stopped by the user, only fetched again when asked to
+    Cancelled,
+    // the download was dropped, fetched again when it is needed
+    Failed(String),
+}
+
+#[derive(Default)]
+pub(crate) struct Attachments {
+    blobs: HashMap<String, BlobState>,
+    last_fetch: u64,
+}
+
+impl Attachments {
+    pub fn get(&self, hash: &str) -> Option<Arc<[u8]>> {
+        match self.blobs.get(hash) {
+            Some(BlobState::Ready(bytes)) => Some(Arc::clone(bytes)),
+            _ => None,
+        }
+    }
+
+    // adds a blob we already have, like one we are uploading ourselves
+    pub fn insert(&mut self, hash: &str, bytes: Arc<[u8]>) {
+        self.blobs.insert(hash.to_string(), BlobState::Ready(bytes));
+    }
+
+    // why a blob isn't coming, None while it is on its way or ready
+    pub fn failure(&self, hash: &str) -> Option<String> {
+        match self.blobs.get(hash)? {
+            BlobState::Missing(_) => Some("the server doesn't have it".to_string()),
+            BlobState::Cancelled => Some("the download was cancelled".to_string()),
+            BlobState::Failed(reason) => Some(reason.clone()),
+            BlobState::Downloading(_) | BlobState::Ready(_) => None,
+        }
+    }
+
+    fn fetch(&mut self, hash: &str, name: &str, size: u64, net: Option<&NetworkClient>) {
+        let Some(net) = net else {
+            return;
+        };
+        self.last_fetch += 1;
+        let id = self.last_fetch;
+        net.send(MessageType::FetchBlob(FetchBlob { hash: hash.to_string(), offset: 0, id }));
+        self.blobs.insert(
+            hash.to_string(),
+            BlobState::Downloading(Download {
+                name: name.to_string(),
+                size,
+                buf: Vec::new(),
+                fetch: id,
+            }),
+        );
+    }
+
+    // true unless the blob is on its way already or the user cancelled it
+    fn should_request(&self, hash: &str, now: f64) -> bool {
+        match self.blobs.get(hash) {
+            None | Some(BlobState::Failed(_)) => true,
+            Some(BlobState::Missing(at)) => now - at > RETRY_AFTER_SECS,
+            Some(_) => false,
+        }
+    }
+
+    // asks the server for an attachment's bytes when they are needed
+    pub fn request(&mut self, attachment: &AttachmentDescriptor, now: f64, net: Option<&NetworkClient>) {
+        self.request_blob(&attachment.hash, &attachment.name, attachment.size, now, net);
+    }
+
+    // the same for blobs that aren't attachments, like stickers
+    pub fn request_blob(&mut self, hash: &str, name: &str, size: u64, now: f64, net: Option<&NetworkClient>) {
+        if self.should_request(hash, now) {
+            self.fetch(hash, name, size, net);
+        }
+    }
+
+    // fetches a blob even if its download was cancelled before
+    pub fn request_again(&mut self, attachment: &AttachmentDescriptor, net: Option<&NetworkClient>) {
+        if !matches!(self.blobs.get(&attachment.hash), Some(BlobState::Downloading(_) | BlobState::Ready(_))) {
+            self.fetch(&attachment.hash, &attachment.name, attachment.size, net);
+        }
+    }
+
+    pub fn cancel(&mut self, hash: &str, net: Option<&NetworkClient>) {
+        if let Some(state @ BlobState::Downloading(_)) = self.blobs.get_mut(hash) {
+            *state = BlobState::Cancelled;
+            if let Some(net) = net {
+                net.send(MessageType::FetchCancel(hash.to_string()));
+            }
+        }
+    }
+
+    // carries on with unfinished downloads after a reconnect
+    pub fn resume(&mut self, net: &NetworkClient) {
+        for (hash, state) in &self.blobs {
+            if let BlobState::Downloading(download) = state {
+                let offset = download.received();
+                net.send(MessageType::FetchBlob(FetchBlob { hash: hash.clone(), offset, id: download.fetch }));
+            }
+        }
+    }
+
+    // downloads in progress, with their hashes
+    pub fn downloads(&self) -> impl Iterator<Item = (&String, &Download)> {
+        self.blobs.iter().filter_map(|(hash, state)| match state {
+            BlobState::Downloading(download) => Some((hash, download)),
+            _ => None,
+        })
+    }
+
+    pub fn receive_chunk(&mut self, chunk: BlobChunk) {
+        let Some(BlobState::Downloading(download)) = self.blobs.get_mut(&chunk.hash) else {
+            return;
+        };
+        // left over from a fetch that was cancelled or started over
+        if chunk.fetch != download.fetch {
+            return;
+        }
+
+        // chunks repeated after a resume are skipped, anything else out of
+        // order means the download has to start over
+        if chunk.offset < download.received() {
+            return;
+        }
+        if chunk.offset != download.received() {
+            eprintln!("unexpected chunk for blob {}", chunk.hash);
+            self.fail(chunk.hash, "the download was interrupted");
+            return;
+        }
+        download.buf.extend_from_slice(&chunk.data);
+
+        if download.received() < chunk.total {
+            return;
+        }
+        let buf = std::mem::take(&mut download.buf);
+        if content_hash(&buf) == chunk.hash {
+            self.insert(&chunk.hash, buf.into());
+        } else {
+            eprintln!("downloaded blob {} doesn't match its hash", chunk.hash);
+            self.fail(chunk.hash, "the download was corrupted");
+        }
+    }
+
+    fn fail(&mut self, hash: String, reason: &str) {
+        self.blobs.insert(hash, BlobState::Failed(reason.to_string()));
+    }
+
+    pub fn missing(&mut self, hash: String, now: f64) {
+        self.blobs.insert(hash, BlobState::Missing(now));
+    }
+
+    // shows a message's attachments, fetching the ones that scroll into view.
+    // images show their thumbnail until the original has been opened
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        attachments: &[AttachmentDescriptor],
+        textures: &mut TextureCache,
+        net: Option<&NetworkClient>,
+    ) -> Option<AttachmentAction> {
+        let mut action = None;
+        for attachment in attachments {
+            if let Some(clicked) = self.show_one(ui, attachment, textures, net) {
+                action = Some(clicked);
+            }
+            if !attachment.caption.is_empty() {
+                ui.label(egui::RichText::new(&attachment.caption).weak());
+            }
+        }
+        action
+    }
+
+    fn show_one(
+        &mut self,
+        ui: &mut egui::Ui,
+        attachment: &AttachmentDescriptor,
+        textures: &mut TextureCache,
+        net: Option<&NetworkClient>,
+    ) -> Option<AttachmentAction> {
+        if !attachment.is_image() {
+            return file_card(ui, attachment).then(|| AttachmentAction::Save(attachment.clone()));
+        }
+
+        let size = egui::vec2(250.0, 250.0);
+        let preview = [Some(attachment.hash.as_str()), attachment.thumbnail.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|hash| textures.get(ui.ctx(), hash, || self.get(hash)));
+        if let Some(texture) = preview {
+            let resp = ui.add(
+                egui::Image::from_texture(texture)
+                    .max_size(size)
+                    .fit_to_exact_size(size)
+                    .sense(egui::Sense::click()),
+            );
+            let clicked = resp.on_hover_cursor(egui::CursorIcon::PointingHand).clicked();
+            return clicked.then(|| AttachmentAction::Open(attachment.clone()));
+        }
+
+        // the thumbnail is fetched instead of the image when there is one
+        let (hash, fetch_size) = match &attachment.thumbnail {
+            Some(thumbnail) => (thumbnail.as_str(), 0),
+            None => (attachment.hash.as_str(), attachment.size),
+        };
+
+        if matches!(self.blobs.get(hash), Some(BlobState::Cancelled)) {
+            if ui.button(format!("download {} ({})", attachment.name, format_size(attachment.size))).clicked() {
+                self.fetch(hash, &attachment.name, fetch_size, net);
+            }
+            return None;
+        }
+
+        let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+        if ui.is_rect_visible(rect) {
+            egui::Spinner::new().paint_at(ui, egui::Rect::from_center_size(rect.center(), egui::vec2(24.0, 24.0)));
+            let now = ui.input(|i| i.time);
+            if self.should_request(hash, now) {
+                self.fetch(hash, &attachment.name, fetch_size, net);
+            }
+        }
+        None
+    }
+}
+
+// what the user asked to do with an attachment in the chat
+pub(crate) enum AttachmentAction {
+    Save(AttachmentDescriptor),
+    Open(AttachmentDescriptor),
+}
+
+// a card for non-image attachments, returns true when "save as" was clicked
+fn file_card(ui: &mut egui::Ui, attachment: &AttachmentDescriptor) -> bool {
+    egui::Frame::group(ui.style())
+        .corner_radius(4)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(attachment.icon()).size(28.0));
+                ui.vertical(|ui| {
+                    ui.label(egui::RichText::new(&attachment.name).strong());
+                    ui.label(
+                        egui::RichText::new(format!("{} · {}", format_size(attachment.size), attachment.mime))
+                            .weak()
+                            .small(),
+                    );
+                });
+                ui.button("save as").clicked()
+            })
+            .inner
+        })
+        .inner
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachment::chunks;
+
+    fn downloading(attachments: &mut Attachments, hash: &str, size: u64, fetch: u64) {
+        let download = Download { name: "file".to_string(), size, buf: Vec::new(), fetch };
+        attachments.blobs.insert(hash.to_string(), BlobState::Downloading(download));
+    }
+
+    fn fetched(hash: &str, bytes: &[u8], fetch: u64) -> Vec<BlobChunk> {
+        chunks(hash, bytes).into_iter().map(|chunk| BlobChunk { fetch, ..chunk }).collect()
+    }
+
+    #[test]
+    fn chunks_from_an_earlier_fetch_are_dropped() {
+        let bytes = vec![7; crate::attachment::CHUNK_SIZE * 2];
+        let hash = content_hash(&bytes);
+        let mut attachments = Attachments::default();
+        downloading(&mut attachments, &hash, bytes.len() as u64, 2);
+
+        let old = fetched(&hash, &bytes, 1);
+        let new = fetched(&hash, &bytes, 2);
+        // the old stream is still going when the new one starts
+        attachments.receive_chunk(new[0].clone());
+        attachments.receive_chunk(old[1].clone());
+        attachments.receive_chunk(old[0].clone());
+        assert!(attachments.failure(&hash).is_none());
+        attachments.receive_chunk(new[1].clone());
+        assert_eq!(attachments.get(&hash).as_deref(), Some(&bytes[..]));
+    }
+
+    #[test]
+    fn dropped_downloads_report_why() {
+        let mut attachments = Attachments::default();
+        let hash = content_hash(b"expected");
+        downloading(&mut attachments, &hash, 8, 1);
+        attachments.receive_chunk(fetched(&hash, b"tampered", 1).remove(0));
+        assert_eq!(attachments.failure(&hash).as_deref(), Some("the download was corrupted"));
+
+        attachments.missing("gone".to_string(), 0.0);
+        assert!(attachments.failure("gone").is_some());
+        assert!(attachments.failure("never asked for").is_none());
+    }
+}
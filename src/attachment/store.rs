@@ -0,0 +1,332 @@
+// server side blob store
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    attachment::{
+        AttachmentDescriptor, BlobChunk, CHUNK_SIZE, MAX_ATTACHMENT_BYTES, chunks, format_size,
+        validate,
+    },
+    hash::content_hash,
+};
+
+// how many bytes of blobs and unfinished uploads the server keeps in memory.
+// the blobs used longest ago are dropped to make room for new ones
+pub const BLOB_BUDGET_BYTES: u64 = 512 * 1024 * 1024;
+
+struct Blob {
+    bytes: Arc<Vec<u8>>,
+    last_used: u64,
+    // blobs the server has itself are never dropped
+    pinned: bool,
+}
+
+pub struct BlobStore {
+    blobs: HashMap<String, Blob>,
+    // uploads in progress, keyed by the hash they should end up with
+    uploads: HashMap<String, Vec<u8>>,
+    // uploads the client gave up on, chunks it had already sent are ignored
+    // until it starts over from the beginning
+    cancelled: HashSet<String>,
+    budget: u64,
+    // bytes held by unpinned blobs and uploads
+    used: u64,
+    clock: u64,
+}
+
+impl Default for BlobStore {
+    fn default() -> Self {
+        Self::with_budget(BLOB_BUDGET_BYTES)
+    }
+}
+
+impl BlobStore {
+    pub fn with_budget(budget: u64) -> Self {
+        Self {
+            blobs: HashMap::new(),
+            uploads: HashMap::new(),
+            cancelled: HashSet::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        self.clock += 1;
+        let blob = self.blobs.get_mut(hash)?;
+        blob.last_used = self.clock;
+        Some(blob.bytes.clone())
+    }
+
+    // stores bytes the server has itself, like its sticker packs
+    pub fn insert(&mut self, bytes: Vec<u8>) -> String {
+        let hash = content_hash(&bytes);
+        self.blobs.insert(hash.clone(), Blob { bytes: Arc::new(bytes), last_used: 0, pinned: true });
+        hash
+    }
+
+    fn store(&mut self, hash: String, bytes: Vec<u8>) {
+        if self.blobs.contains_key(&hash) {
+            return;
+        }
+        self.clock += 1;
+        self.used += bytes.len() as u64;
+        self.blobs.insert(hash, Blob { bytes: Arc::new(bytes), last_used: self.clock, pinned: false });
+    }
+
+    // drops the blobs used longest ago until `extra` more bytes fit
+    fn make_room(&mut self, extra: u64) -> Result<(), String> {
+        while self.used + extra > self.budget {
+            let oldest = self
+                .blobs
+                .iter()
+                .filter(|(_, blob)| !blob.pinned)
+                .min_by_key(|(_, blob)| blob.last_used)
+                .map(|(hash, _)| hash.clone());
+            let Some(hash) = oldest else {
+                return Err("the server has no room for more attachments".to_string());
+            };
+            if let Some(blob) = self.blobs.remove(&hash) {
+                self.used -= blob.bytes.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    // adds an uploaded chunk and returns how much of the blob is stored.
+    // chunks that don't continue the upload are ignored, the returned count
+    // tells the client where to carry on from
+    pub fn receive_chunk(&mut self, chunk: BlobChunk) -> Result<u64, String> {
+        if self.blobs.contains_key(&chunk.hash) {
+            return Ok(chunk.total);
+        }
+        if chunk.total > MAX_ATTACHMENT_BYTES {
+            return Err(format!(
+                "attachments are limited to {}",
+                format_size(MAX_ATTACHMENT_BYTES)
+            ));
+        }
+        if chunk.data.len() > CHUNK_SIZE {
+            return Err(format!("chunk of {} bytes is too large", chunk.data.len()));
+        }
+
+        if chunk.offset == 0 {
+            self.cancelled.remove(&chunk.hash);
+        } else if self.cancelled.contains(&chunk.hash) {
+            return Ok(0);
+        }
+
+        let stored = self.received(&chunk.hash);
+        if chunk.offset != stored {
+            return Ok(stored);
+        }
+        if stored + chunk.data.len() as u64 > chunk.total {
+            self.drop_upload(&chunk.hash);
+            return Err("upload is larger than announced".to_string());
+        }
+        if let Err(e) = self.make_room(chunk.data.len() as u64) {
+            self.drop_upload(&chunk.hash);
+            return Err(e);
+        }
+        let upload = self.uploads.entry(chunk.hash.clone()).or_default();
+        upload.extend_from_slice(&chunk.data);
+        self.used += chunk.data.len() as u64;
+
+        let received = upload.len() as u64;
+        if received == chunk.total {
+            let bytes = self.uploads.remove(&chunk.hash).unwrap_or_default();
+            self.used -= bytes.len() as u64;
+            if content_hash(&bytes) != chunk.hash {
+                return Err("uploaded bytes don't match their hash".to_string());
+            }
+            validate::check_file(&bytes)?;
+            self.store(chunk.hash, bytes);
+        }
+        Ok(received)
+    }
+
+    // checks an attachment of a message against its stored blob before the
+    // message goes out. the type and dimensions are taken from the bytes, and
+    // jpegs are replaced by a copy without metadata, under its own hash
+    pub fn sanitize(&mut self, attachment: &AttachmentDescriptor) -> Result<AttachmentDescriptor, String> {
+        let mut clean = attachment.clone();
+        let (hash, size) = self.clean_blob(&attachment.hash)?;
+        clean.hash = hash;
+        clean.size = size;
+
+        let blob = self.get(&clean.hash).unwrap_or_default();
+        match validate::check_image(&blob) {
+            Ok(info) => {
+                clean.mime = info.format.to_mime_type().to_string();
+                clean.width = Some(info.width);
+                clean.height = Some(info.height);
+            },
+            // clients shouldn't try to show something that isn't an image
+            Err(_) if clean.is_image() => {
+                clean.mime = "application/octet-stream".to_string();
+                clean.width = None;
+                clean.height = None;
+            },
+            Err(_) => {},
+        }
+
+        clean.thumbnail = attachment
+            .thumbnail
+            .as_deref()
+            .and_then(|hash| self.clean_blob(hash).ok())
+            .map(|(hash, _)| hash)
+            .filter(|hash| self.get(hash).is_some_and(|t| validate::check_image(&t).is_ok()));
+        Ok(clean)
+    }
+
+    // the hash and size of a stored blob once jpeg metadata is stripped
+    pub fn clean_blob(&mut self, hash: &str) -> Result<(String, u64), String> {
+        let blob = self.get(hash).ok_or("the attachment was never uploaded")?;
+        validate::check_file(&blob)?;
+        if validate::sniff(&blob) != Some(image::ImageFormat::Jpeg) {
+            return Ok((hash.to_string(), blob.len() as u64));
+        }
+
+        let stripped = validate::strip_jpeg_metadata(&blob).ok_or("the JPEG is malformed")?;
+        if stripped.len() == blob.len() {
+            return Ok((hash.to_string(), blob.len() as u64));
+        }
+        let size = stripped.len() as u64;
+        let hash = content_hash(&stripped);
+        if !self.blobs.contains_key(&hash) {
+            self.make_room(size)?;
+            self.store(hash.clone(), stripped);
+        }
+        Ok((hash, size))
+    }
+
+    // bytes received so far for a blob
+    pub fn received(&self, hash: &str) -> u64 {
+        match self.blobs.get(hash) {
+            Some(blob) => blob.bytes.len() as u64,
+            None => self.uploads.get(hash).map_or(0, |u| u.len() as u64),
+        }
+    }
+
+    // throws away an unfinished upload
+    pub fn cancel_upload(&mut self, hash: &str) {
+        if !self.blobs.contains_key(hash) {
+            self.drop_upload(hash);
+            self.cancelled.insert(hash.to_string());
+        }
+    }
+
+    fn drop_upload(&mut self, hash: &str) {
+        if let Some(upload) = self.uploads.remove(hash) {
+            self.used -= upload.len() as u64;
+        }
+    }
+
+    // chunks to send a stored blob to a client, starting at `offset`
+    pub fn download(&mut self, hash: &str, offset: u64) -> Option<Vec<BlobChunk>> {
+        self.get(hash).map(|blob| {
+            chunks(hash, &blob)
+                .into_iter()
+                .filter(|c| c.offset >= offset)
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode_image;
+
+    fn upload(store: &mut BlobStore, bytes: &[u8]) -> Result<String, String> {
+        let hash = content_hash(bytes);
+        for chunk in chunks(&hash, bytes) {
+            store.receive_chunk(chunk)?;
+        }
+        Ok(hash)
+    }
+
+    #[test]
+    fn jpeg_metadata_is_stripped_before_broadcast() {
+        let jpeg = encode_image(8, 8, image::ImageFormat::Jpeg);
+        let exif = [&[0xFF, 0xE1, 0x00, 0x0B][..], b"Exif\0\0GPS"].concat();
+        let photo = [&jpeg[..2], &exif, &jpeg[2..]].concat();
+
+        let mut store = BlobStore::default();
+        let hash = upload(&mut store, &photo).unwrap();
+        let descriptor = AttachmentDescriptor { hash: hash.clone(), name: "photo.jpg".to_string(), ..Default::default() };
+
+        let clean = store.sanitize(&descriptor).unwrap();
+        assert_ne!(clean.hash, hash);
+        assert_eq!(clean.mime, "image/jpeg");
+        assert_eq!((clean.width, clean.height), (Some(8), Some(8)));
+        let blob = store.get(&clean.hash).unwrap();
+        assert_eq!(blob.len() as u64, clean.size);
+        assert!(!blob.windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn oversized_images_are_rejected_on_upload() {
+        let png = encode_image(validate::MAX_IMAGE_SIDE + 1, 1, image::ImageFormat::Png);
+        let mut store = BlobStore::default();
+        assert!(upload(&mut store, &png).is_err());
+    }
+
+    #[test]
+    fn files_that_claim_to_be_images_are_sent_as_files() {
+        let mut store = BlobStore::default();
+        let hash = upload(&mut store, b"definitely not a png").unwrap();
+        let descriptor = AttachmentDescriptor {
+            hash,
+            mime: "image/png".to_string(),
+            width: Some(10),
+            height: Some(10),
+            ..Default::default()
+        };
+
+        let clean = store.sanitize(&descriptor).unwrap();
+        assert!(!clean.is_image());
+        assert_eq!(clean.width, None);
+    }
+
+    #[test]
+    fn chunks_after_a_cancel_are_ignored() {
+        let bytes = vec![5; CHUNK_SIZE * 3];
+        let hash = content_hash(&bytes);
+        let parts = chunks(&hash, &bytes);
+        let mut store = BlobStore::default();
+
+        store.receive_chunk(parts[0].clone()).unwrap();
+        store.cancel_upload(&hash);
+        // already on their way when the cancel arrived
+        assert_eq!(store.receive_chunk(parts[1].clone()), Ok(0));
+        assert_eq!(store.receive_chunk(parts[2].clone()), Ok(0));
+        assert_eq!(store.received(&hash), 0);
+
+        // sending the same file again starts over
+        assert_eq!(upload(&mut store, &bytes), Ok(hash.clone()));
+        assert!(store.get(&hash).is_some());
+    }
+
+    #[test]
+    fn least_recently_used_blobs_are_dropped_over_budget() {
+        let mut store = BlobStore::with_budget(25);
+        let sticker = store.insert(vec![0; 20]);
+        let first = upload(&mut store, &[1; 10]).unwrap();
+        let second = upload(&mut store, &[2; 10]).unwrap();
+        store.get(&first);
+
+        let third = upload(&mut store, &[3; 10]).unwrap();
+        assert!(store.get(&second).is_none());
+        assert!(store.get(&first).is_some());
+        assert!(store.get(&third).is_some());
+        assert!(store.get(&sticker).is_some());
+
+        // an upload that can't fit even with everything else dropped
+        assert!(upload(&mut store, &[4; 30]).is_err());
+        assert_eq!(store.received(&content_hash(&[4; 30])), 0);
+    }
+}
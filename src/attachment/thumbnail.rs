@@ -0,0 +1,159 @@
+// images are shrunk before they are sent. the chat shows a small thumbnail
+// and the full image, downscaled when it is huge, is only fetched when opened
+use std::io::Cursor;
+
+use image::{
+    DynamicImage, ImageDecoder, ImageFormat, codecs::jpeg::JpegEncoder,
+    metadata::Orientation,
+};
+
+use crate::attachment::validate;
+
+// longest side of the thumbnail shown in the chat
+pub const THUMBNAIL_SIZE: u32 = 320;
+// longest side of a recompressed image
+pub const MAX_IMAGE_SIZE: u32 = 2048;
+// smaller files are only re-encoded when their dimensions are too big
+const RECOMPRESS_ABOVE_BYTES: usize = 1024 * 1024;
+const JPEG_QUALITY: u8 = 85;
+
+// a still image ready to be sent, `bytes` may be a smaller re-encode of the
+// picked file
+pub struct PreparedImage {
+    pub name: String,
+    pub bytes: Vec<u8>,
+    pub thumbnail: Option<Vec<u8>>,
+}
+
+// decodes a jpeg or png the right way up, also saying whether it had to be
+// turned. animated formats are left alone, resizing them would keep only the
+// first frame
+fn decode(bytes: &[u8]) -> Option<(DynamicImage, bool)> {
+    let format = validate::sniff(bytes).filter(|f| *f != ImageFormat::Gif)?;
+    let mut decoder = validate::limited_reader(bytes, format, validate::LOCAL_LIMITS).into_decoder().ok()?;
+    let orientation = decoder.orientation().ok()?;
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    image.apply_orientation(orientation);
+    Some((image, orientation != Orientation::NoTransforms))
+}
+
+// encodes as jpeg unless the image has transparency, returns the extension
+fn encode(image: &DynamicImage) -> Option<(Vec<u8>, &'static str)> {
+    let mut out = Cursor::new(Vec::new());
+    if image.color().has_alpha() {
+        image.write_to(&mut out, ImageFormat::Png).ok()?;
+        Some((out.into_inner(), "png"))
+    } else {
+        JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)
+            .encode_image(&image.to_rgb8())
+            .ok()?;
+        Some((out.into_inner(), "jpg"))
+    }
+}
+
+fn with_extension(name: &str, extension: &str) -> String {
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    format!("{stem}.{extension}")
+}
+
+// makes a thumbnail and, when `recompress` is set, a smaller full image.
+// images bigger than others may decode are always shrunk to fit. jpegs lose
+// their metadata. None when the file isn't a still image we can decode
+pub fn prepare(name: &str, bytes: &[u8], recompress: bool) -> Option<PreparedImage> {
+    let (image, turned) = decode(bytes)?;
+    let longest = image.width().max(image.height());
+    let fits = validate::NETWORK_LIMITS.allows(image.width(), image.height());
+
+    let mut prepared = PreparedImage {
+        name: name.to_string(),
+        bytes: bytes.to_vec(),
+        thumbnail: None,
+    };
+
+    let shrink = recompress && (longest > MAX_IMAGE_SIZE || bytes.len() > RECOMPRESS_ABOVE_BYTES);
+    // stripping the metadata loses the orientation, so turned images are
+    // re-encoded the right way up
+    if shrink || turned || !fits {
+        let resized = if shrink && longest > MAX_IMAGE_SIZE {
+            image.resize(MAX_IMAGE_SIZE, MAX_IMAGE_SIZE, image::imageops::FilterType::Lanczos3)
+        } else if !fits {
+            let (width, height) = validate::NETWORK_LIMITS.fit(image.width(), image.height());
+            image.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+        // a re-encode that isn't smaller isn't worth the quality loss
+        if let Some((encoded, extension)) = encode(&resized)
+            && (turned || !fits || encoded.len() < bytes.len())
+        {
+            prepared.name = with_extension(name, extension);
+            prepared.bytes = encoded;
+        }
+    }
+
+    if validate::sniff(&prepared.bytes) == Some(ImageFormat::Jpeg) {
+        prepared.bytes = validate::strip_jpeg_metadata(&prepared.bytes)?;
+    }
+
+    // small images are their own thumbnail
+    if longest > THUMBNAIL_SIZE
+        && let Some((thumbnail, _)) = encode(&image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+    {
+        prepared.thumbnail = Some(thumbnail);
+    }
+
+    Some(prepared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode_image;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        encode_image(width, height, ImageFormat::Png)
+    }
+
+    fn dimensions(bytes: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(bytes).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn large_images_get_a_bounded_thumbnail() {
+        let prepared = prepare("photo.png", &png(1200, 600), false).unwrap();
+        let thumbnail = prepared.thumbnail.unwrap();
+        assert_eq!(dimensions(&thumbnail), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert_eq!(prepared.name, "photo.png");
+    }
+
+    #[test]
+    fn small_images_are_sent_unchanged() {
+        let bytes = png(100, 80);
+        let prepared = prepare("icon.png", &bytes, true).unwrap();
+        assert_eq!(prepared.bytes, bytes);
+        assert!(prepared.thumbnail.is_none());
+    }
+
+    #[test]
+    fn huge_images_are_downscaled_when_recompressing() {
+        let prepared = prepare("scan.png", &png(MAX_IMAGE_SIZE * 2, 64), true).unwrap();
+        assert_eq!(dimensions(&prepared.bytes), (MAX_IMAGE_SIZE, 32));
+        assert_eq!(prepared.name, "scan.jpg");
+    }
+
+    #[test]
+    fn images_just_over_the_limit_are_shrunk_to_fit() {
+        let panorama = png(validate::MAX_IMAGE_SIDE + 1, 2);
+        assert!(validate::check_local_file(&panorama).is_ok());
+
+        let prepared = prepare("panorama.png", &panorama, false).unwrap();
+        assert_eq!(dimensions(&prepared.bytes), (validate::MAX_IMAGE_SIDE, 1));
+        assert!(validate::check_file(&prepared.bytes).is_ok());
+    }
+
+    #[test]
+    fn other_files_are_not_images() {
+        assert!(prepare("notes.txt", b"hello", true).is_none());
+    }
+}
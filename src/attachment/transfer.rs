@@ -0,0 +1,282 @@
+// client side upload manager. uploads are driven by the server's acks so they
+// can be cancelled, and resumed from the last acked chunk after a reconnect
+use std::sync::Arc;
+
+use crate::{
+    attachment::{BlobChunk, CHUNK_SIZE, UploadAck},
+    message::{Message, MessageType},
+    network::client::NetworkClient,
+};
+
+// chunks that may be sent ahead of the server's acks
+const WINDOW: u64 = 4;
+
+#[derive(PartialEq)]
+enum UploadState {
+    Active,
+    // waiting for the server to say how much it has after a reconnect
+    Resuming,
+}
+
+pub(crate) struct Upload {
+    pub name: String,
+    pub hash: String,
+    bytes: Arc<[u8]>,
+    // bytes the server has confirmed
+    pub acked: u64,
+    // bytes handed to the network so far
+    sent: u64,
+    state: UploadState,
+}
+
+impl Upload {
+    pub fn total(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.acked as f32 / self.total().max(1) as f32
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Transfers {
+    pub uploads: Vec<Upload>,
+    // messages held back until their attachments are uploaded
+    queued: Vec<Message>,
+}
+
+impl Transfers {
+    pub fn start_upload(&mut self, name: String, hash: String, bytes: Arc<[u8]>) {
+        if self.uploads.iter().any(|u| u.hash == hash) {
+            return;
+        }
+        self.uploads.push(Upload {
+            name,
+            hash,
+            bytes,
+            acked: 0,
+            sent: 0,
+            state: UploadState::Active,
+        });
+    }
+
+    pub fn queue_message(&mut self, message: Message) {
+        self.queued.push(message);
+    }
+
+    pub fn ack(&mut self, ack: UploadAck) {
+        if let Some(upload) = self.uploads.iter_mut().find(|u| u.hash == ack.hash) {
+            if upload.state == UploadState::Resuming {
+                upload.state = UploadState::Active;
+                upload.sent = ack.received;
+            }
+            upload.acked = upload.acked.max(ack.received);
+            upload.sent = upload.sent.max(upload.acked);
+        }
+    }
+
+    // stops an upload and takes its attachment off the waiting messages,
+    // returns the upload's name
+    fn remove(&mut self, hash: &str) -> Option<String> {
+        let index = self.uploads.iter().position(|u| u.hash == hash)?;
+        let upload = self.uploads.remove(index);
+        // a missing thumbnail only costs the preview, not the attachment
+        for message in &mut self.queued {
+            message.attachments.retain(|a| a.hash != hash);
+            if message.sticker.as_ref().is_some_and(|s| s.hash == hash) {
+                message.sticker = None;
+            }
+            for attachment in &mut message.attachments {
+                if attachment.thumbnail.as_deref() == Some(hash) {
+                    attachment.thumbnail = None;
+                }
+            }
+        }
+        // messages with nothing left to say are dropped
+        self.queued
+            .retain(|m| !m.attachments.is_empty() || m.sticker.is_some() || !m.message.trim().is_empty());
+        Some(upload.name)
+    }
+
+    pub fn cancel(&mut self, hash: &str, net: Option<&NetworkClient>) {
+        if self.remove(hash).is_some()
+            && let Some(net) = net
+        {
+            net.send(MessageType::UploadCancel(hash.to_string()));
+        }
+    }
+
+    // the server refused the upload, returns its name
+    pub fn reject(&mut self, hash: &str) -> Option<String> {
+        self.remove(hash)
+    }
+
+    // asks the server where to carry on after a reconnect
+    pub fn resume(&mut self, net: &NetworkClient) {
+        for upload in &mut self.uploads {
+            upload.state = UploadState::Resuming;
+            net.send(MessageType::UploadResume(upload.hash.clone()));
+        }
+    }
+
+    // sends the next chunks of every upload and the messages whose uploads
+    // are finished, called every frame
+    pub fn pump(&mut self, net: &NetworkClient) {
+        for upload in &mut self.uploads {
+            if upload.state != UploadState::Active {
+                continue;
+            }
+            while upload.sent < upload.total() && upload.sent < upload.acked + WINDOW * CHUNK_SIZE as u64 {
+                let start = upload.sent as usize;
+                let end = (start + CHUNK_SIZE).min(upload.bytes.len());
+                let chunk = BlobChunk {
+                    hash: upload.hash.clone(),
+                    offset: upload.sent,
+                    total: upload.total(),
+                    data: upload.bytes[start..end].to_vec(),
+                    fetch: 0,
+                };
+                // the bulk channel is full, try again next frame
+                if !net.try_send_bulk(MessageType::UploadChunk(chunk)) {
+                    break;
+                }
+                upload.sent = end as u64;
+            }
+        }
+        self.uploads.retain(|u| u.acked < u.total());
+
+        let uploading: Vec<&str> = self.uploads.iter().map(|u| u.hash.as_str()).collect();
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.queued)
+            .into_iter()
+            .partition(|m| {
+                m.attachments
+                    .iter()
+                    .flat_map(|a| a.blob_hashes())
+                    .chain(m.sticker.as_ref().map(|s| s.hash.as_str()))
+                    .all(|hash| !uploading.contains(&hash))
+            });
+        self.queued = waiting;
+        for message in ready {
+            net.send(MessageType::Message(message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachment::AttachmentDescriptor;
+    use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+
+    const CHUNK: u64 = CHUNK_SIZE as u64;
+
+    fn upload_of(chunks: usize) -> Transfers {
+        let mut transfers = Transfers::default();
+        let bytes = vec![1; CHUNK_SIZE * chunks];
+        transfers.start_upload("file".to_string(), "hash".to_string(), bytes.into());
+        transfers
+    }
+
+    // offsets of the chunks sent since the last call
+    fn sent_offsets(bulk: &mut Receiver<MessageType>) -> Vec<u64> {
+        std::iter::from_fn(|| bulk.try_recv().ok())
+            .map(|msg| match msg {
+                MessageType::UploadChunk(chunk) => chunk.offset / CHUNK,
+                _ => panic!("only chunks go out as bulk traffic"),
+            })
+            .collect()
+    }
+
+    fn drain(messages: &mut UnboundedReceiver<MessageType>) -> Vec<MessageType> {
+        std::iter::from_fn(|| messages.try_recv().ok()).collect()
+    }
+
+    fn ack(transfers: &mut Transfers, chunks: u64) {
+        transfers.ack(UploadAck { hash: "hash".to_string(), received: chunks * CHUNK });
+    }
+
+    fn message_with_attachment() -> Message {
+        Message {
+            message: String::new(),
+            attachments: vec![AttachmentDescriptor { hash: "hash".to_string(), ..Default::default() }],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_stay_within_the_window_of_acks() {
+        let (net, _, mut bulk) = NetworkClient::detached();
+        let mut transfers = upload_of(10);
+
+        transfers.pump(&net);
+        assert_eq!(sent_offsets(&mut bulk), [0, 1, 2, 3]);
+        transfers.pump(&net);
+        assert!(sent_offsets(&mut bulk).is_empty());
+
+        ack(&mut transfers, 2);
+        transfers.pump(&net);
+        assert_eq!(sent_offsets(&mut bulk), [4, 5]);
+        assert_eq!(transfers.uploads[0].progress(), 0.2);
+
+        // acks that arrive out of order don't move the upload back
+        ack(&mut transfers, 1);
+        assert_eq!(transfers.uploads[0].acked, 2 * CHUNK);
+    }
+
+    #[tokio::test]
+    async fn messages_wait_for_their_uploads() {
+        let (net, mut messages, mut bulk) = NetworkClient::detached();
+        let mut transfers = upload_of(2);
+        transfers.queue_message(message_with_attachment());
+
+        transfers.pump(&net);
+        assert!(drain(&mut messages).is_empty());
+
+        ack(&mut transfers, 2);
+        transfers.pump(&net);
+        assert!(transfers.uploads.is_empty());
+        assert!(matches!(drain(&mut messages).as_slice(), [MessageType::Message(_)]));
+        assert_eq!(sent_offsets(&mut bulk), [0, 1]);
+    }
+
+    #[tokio::test]
+    async fn cancel_tells_the_server_and_drops_the_attachment() {
+        let (net, mut messages, _bulk) = NetworkClient::detached();
+        let mut transfers = upload_of(3);
+        let mut with_text = message_with_attachment();
+        with_text.message = "look".to_string();
+        transfers.queue_message(with_text);
+        transfers.queue_message(message_with_attachment());
+
+        transfers.cancel("hash", Some(&net));
+        assert!(transfers.uploads.is_empty());
+        assert!(matches!(drain(&mut messages).as_slice(), [MessageType::UploadCancel(hash)] if hash == "hash"));
+
+        // the text is still sent, the message that only had the file is not
+        transfers.pump(&net);
+        match drain(&mut messages).as_slice() {
+            [MessageType::Message(m)] => assert!(m.attachments.is_empty() && m.message == "look"),
+            other => panic!("expected the text message, got {} messages", other.len()),
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_carries_on_from_the_server_offset() {
+        let (net, mut messages, mut bulk) = NetworkClient::detached();
+        let mut transfers = upload_of(8);
+        transfers.pump(&net);
+        sent_offsets(&mut bulk);
+
+        // reconnected: nothing goes out until the server says what it has
+        transfers.resume(&net);
+        assert!(matches!(drain(&mut messages).as_slice(), [MessageType::UploadResume(_)]));
+        transfers.pump(&net);
+        assert!(sent_offsets(&mut bulk).is_empty());
+
+        // the server only got three chunks before the connection dropped
+        ack(&mut transfers, 3);
+        transfers.pump(&net);
+        assert_eq!(sent_offsets(&mut bulk), [3, 4, 5, 6]);
+    }
+}
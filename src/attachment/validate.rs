@@ -0,0 +1,268 @@
+// checks made on image bytes by the sender and the server before an image
+// reaches anyone's decoder, and removal of the metadata photos carry
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageReader, Limits};
+
+// a small file can claim dimensions that decode to gigabytes
+pub const MAX_IMAGE_SIDE: u32 = 8192;
+pub const MAX_IMAGE_PIXELS: u64 = 40_000_000;
+// the sender's own photos and panoramas may be bigger, they are downscaled
+// to the limits above before they are sent
+pub const MAX_LOCAL_IMAGE_SIDE: u32 = 32_000;
+pub const MAX_LOCAL_IMAGE_PIXELS: u64 = 120_000_000;
+// pixels over all the frames of an animation
+pub const MAX_ANIMATION_PIXELS: u64 = 50_000_000;
+
+// jpeg markers
+const SOI: u8 = 0xD8;
+const EOI: u8 = 0xD9;
+const SOS: u8 = 0xDA;
+const APP1: u8 = 0xE1;
+const APP13: u8 = 0xED;
+const COM: u8 = 0xFE;
+
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+// how big an image may be before it is decoded
+#[derive(Clone, Copy)]
+pub struct ImageLimits {
+    pub side: u32,
+    pub pixels: u64,
+}
+
+// for bytes that arrive over the network
+pub const NETWORK_LIMITS: ImageLimits = ImageLimits { side: MAX_IMAGE_SIDE, pixels: MAX_IMAGE_PIXELS };
+// for files the user picked themselves
+pub const LOCAL_LIMITS: ImageLimits = ImageLimits { side: MAX_LOCAL_IMAGE_SIDE, pixels: MAX_LOCAL_IMAGE_PIXELS };
+
+impl ImageLimits {
+    pub fn allows(&self, width: u32, height: u32) -> bool {
+        width <= self.side && height <= self.side && width as u64 * height as u64 <= self.pixels
+    }
+
+    // the largest size with the same aspect ratio that is allowed
+    pub fn fit(&self, width: u32, height: u32) -> (u32, u32) {
+        if self.allows(width, height) {
+            return (width, height);
+        }
+        let by_side = self.side as f64 / width.max(height) as f64;
+        let by_pixels = (self.pixels as f64 / (width as f64 * height as f64)).sqrt();
+        let scale = by_side.min(by_pixels);
+        let scaled = |n: u32| ((n as f64 * scale).floor() as u32).max(1);
+        (scaled(width), scaled(height))
+    }
+}
+
+// the image format of some bytes, going by their content rather than the
+// file name. only formats every client can show count
+pub fn sniff(bytes: &[u8]) -> Option<ImageFormat> {
+    match image::guess_format(bytes).ok()? {
+        format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif) => Some(format),
+        _ => None,
+    }
+}
+
+// reads an image's header and rejects it if it is too big to decode safely
+pub fn check_image(bytes: &[u8]) -> Result<ImageInfo, String> {
+    check_image_within(bytes, NETWORK_LIMITS)
+}
+
+pub fn check_image_within(bytes: &[u8], limits: ImageLimits) -> Result<ImageInfo, String> {
+    let format = sniff(bytes).ok_or("not a supported image format")?;
+    let (width, height) = ImageReader::with_format(Cursor::new(bytes), format)
+        .into_dimensions()
+        .map_err(|e| format!("unreadable image: {e}"))?;
+
+    if width == 0 || height == 0 {
+        return Err("the image is empty".to_string());
+    }
+    if !limits.allows(width, height) {
+        return Err(format!("the image is too large ({width}×{height})"));
+    }
+    Ok(ImageInfo { format, width, height })
+}
+
+// files that aren't images pass, images must be safe to decode and jpegs
+// well formed enough to strip
+pub fn check_file(bytes: &[u8]) -> Result<(), String> {
+    check_file_within(bytes, NETWORK_LIMITS)
+}
+
+// the same for a file the user is about to send. still images get the
+// larger local limits since they are downscaled before they go out,
+// animations can't be and have to fit already
+pub fn check_local_file(bytes: &[u8]) -> Result<(), String> {
+    match sniff(bytes) {
+        Some(ImageFormat::Gif) => check_file(bytes),
+        _ => check_file_within(bytes, LOCAL_LIMITS),
+    }
+}
+
+fn check_file_within(bytes: &[u8], limits: ImageLimits) -> Result<(), String> {
+    if sniff(bytes).is_none() {
+        return Ok(());
+    }
+    let info = check_image_within(bytes, limits)?;
+    if info.format == ImageFormat::Jpeg && strip_jpeg_metadata(bytes).is_none() {
+        return Err("the JPEG is malformed".to_string());
+    }
+    Ok(())
+}
+
+// a reader that refuses to allocate more than an image within the limits needs
+pub fn limited_reader(bytes: &[u8], format: ImageFormat, image_limits: ImageLimits) -> ImageReader<Cursor<&[u8]>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(image_limits.side);
+    limits.max_image_height = Some(image_limits.side);
+    // rgba at 16 bits per channel
+    limits.max_alloc = Some(image_limits.pixels * 8);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    reader
+}
+
+// removes exif (which holds gps positions), xmp, iptc and comments from a
+// jpeg. colour profiles and the adobe marker are kept since they change how
+// the image looks. None if the bytes aren't a well formed jpeg
+pub fn strip_jpeg_metadata(bytes: &[u8]) -> Option<Vec<u8>> {
+    if bytes.get(..2)? != [0xFF, SOI] {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut i = 2;
+
+    loop {
+        if *bytes.get(i)? != 0xFF {
+            return None;
+        }
+        // markers can be padded with extra 0xFF bytes
+        while *bytes.get(i + 1)? == 0xFF {
+            i += 1;
+        }
+        let marker = bytes[i + 1];
+
+        match marker {
+            // the compressed image data follows, copied as it is
+            SOS => {
+                out.extend_from_slice(&bytes[i..]);
+                return Some(out);
+            },
+            EOI => {
+                out.extend_from_slice(&bytes[i..i + 2]);
+                return Some(out);
+            },
+            // markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&bytes[i..i + 2]);
+                i += 2;
+            },
+            _ => {
+                let len = u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize;
+                if len < 2 {
+                    return None;
+                }
+                let segment = bytes.get(i..i + 2 + len)?;
+                if !matches!(marker, APP1 | APP13 | COM) {
+                    out.extend_from_slice(segment);
+                }
+                i += 2 + len;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode_image;
+
+    // a jpeg with an exif segment holding a fake gps tag right after SOI
+    fn jpeg_with_exif() -> Vec<u8> {
+        let jpeg = encode_image(16, 8, ImageFormat::Jpeg);
+
+        let payload = b"Exif\0\0GPS 51.5007N 0.1246W";
+        let mut exif = vec![0xFF, APP1];
+        exif.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        exif.extend_from_slice(payload);
+
+        [&jpeg[..2], &exif, &jpeg[2..]].concat()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[test]
+    fn exif_is_stripped_from_jpegs() {
+        let jpeg = jpeg_with_exif();
+        assert!(contains(&jpeg, b"GPS"));
+
+        let stripped = strip_jpeg_metadata(&jpeg).unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        assert!(!contains(&stripped, b"GPS"));
+
+        let image = image::load_from_memory(&stripped).unwrap();
+        assert_eq!((image.width(), image.height()), (16, 8));
+    }
+
+    #[test]
+    fn jpegs_without_metadata_are_unchanged() {
+        let jpeg = encode_image(4, 4, ImageFormat::Jpeg);
+        assert_eq!(strip_jpeg_metadata(&jpeg).unwrap(), jpeg);
+    }
+
+    #[test]
+    fn truncated_jpegs_are_rejected() {
+        let jpeg = jpeg_with_exif();
+        assert!(strip_jpeg_metadata(&jpeg[..10]).is_none());
+        assert!(strip_jpeg_metadata(b"not a jpeg").is_none());
+    }
+
+    #[test]
+    fn formats_are_sniffed_from_content() {
+        let png = encode_image(2, 2, ImageFormat::Png);
+        assert_eq!(sniff(&png), Some(ImageFormat::Png));
+        assert_eq!(sniff(b"%PDF-1.7"), None);
+    }
+
+    #[test]
+    fn oversized_images_are_rejected() {
+        let wide = encode_image(MAX_IMAGE_SIDE + 1, 1, ImageFormat::Png);
+        assert!(check_image(&wide).is_err());
+
+        let fine = encode_image(64, 32, ImageFormat::Png);
+        let info = check_image(&fine).unwrap();
+        assert_eq!((info.width, info.height), (64, 32));
+    }
+
+    #[test]
+    fn local_files_may_be_just_over_the_network_limit() {
+        let panorama = encode_image(MAX_IMAGE_SIDE + 1, 1, ImageFormat::Png);
+        assert!(check_file(&panorama).is_err());
+        assert!(check_local_file(&panorama).is_ok());
+
+        // gifs are sent as they are, so they get no extra room
+        let gif = encode_image(MAX_IMAGE_SIDE + 1, 1, ImageFormat::Gif);
+        assert!(check_local_file(&gif).is_err());
+
+        let too_wide = encode_image(MAX_LOCAL_IMAGE_SIDE + 1, 1, ImageFormat::Png);
+        assert!(check_local_file(&too_wide).is_err());
+    }
+
+    #[test]
+    fn sizes_are_fit_within_the_limits() {
+        assert_eq!(NETWORK_LIMITS.fit(640, 480), (640, 480));
+        assert_eq!(NETWORK_LIMITS.fit(MAX_IMAGE_SIDE * 2, 100), (MAX_IMAGE_SIDE, 50));
+        // a 48 megapixel photo
+        let (width, height) = NETWORK_LIMITS.fit(8000, 6000);
+        assert!(width as u64 * height as u64 <= MAX_IMAGE_PIXELS);
+        assert!((width * 3).abs_diff(height * 4) < 4, "{width}×{height}");
+    }
+}
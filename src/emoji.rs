@@ -0,0 +1,432 @@
+// the emoji picker's data: categories, search, skin tones, the recently used
+// row and :shortcode: expansion for the composer. also the server's custom
+// emoji, which stay as :name: in the text and are drawn as images
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+use emojis::{Emoji, Group, SkinTone};
+
+use crate::{
+    attachment::validate,
+    hash::content_hash,
+    message::{CustomEmoji, CustomEmojiList},
+};
+
+// how many emoji the recently used row remembers
+const MAX_RECENT: usize = 24;
+// largest image the server accepts as a custom emoji
+pub const MAX_CUSTOM_EMOJI_BYTES: usize = 256 * 1024;
+// custom emoji a server keeps, every client gets all of them when it joins
+pub const MAX_CUSTOM_EMOJI: usize = 200;
+// search results shown at once
+const MAX_RESULTS: usize = 120;
+
+// the skin tones offered in the picker, mixed tones are left out
+pub const SKIN_TONES: [SkinTone; 6] = [
+    SkinTone::Default,
+    SkinTone::Light,
+    SkinTone::MediumLight,
+    SkinTone::Medium,
+    SkinTone::MediumDark,
+    SkinTone::Dark,
+];
+
+// icon and name of a category tab
+pub fn group_label(group: Group) -> (&'static str, &'static str) {
+    match group {
+        Group::SmileysAndEmotion => ("😀", "Smileys & emotion"),
+        Group::PeopleAndBody => ("👋", "People & body"),
+        Group::AnimalsAndNature => ("🐻", "Animals & nature"),
+        Group::FoodAndDrink => ("🍔", "Food & drink"),
+        Group::TravelAndPlaces => ("🚗", "Travel & places"),
+        Group::Activities => ("⚽", "Activities"),
+        Group::Objects => ("💡", "Objects"),
+        Group::Symbols => ("💯", "Symbols"),
+        Group::Flags => ("🏁", "Flags"),
+    }
+}
+
+// the emoji in the given skin tone, or as is when it doesn't have tones
+pub fn with_tone(emoji: &'static Emoji, tone: SkinTone) -> &'static Emoji {
+    emoji.with_skin_tone(tone).unwrap_or(emoji)
+}
+
+// what the picker shows when hovering an emoji
+pub fn hover_text(emoji: &Emoji) -> String {
+    match emoji.shortcode() {
+        Some(code) => format!("{} :{code}:", emoji.name()),
+        None => emoji.name().to_string(),
+    }
+}
+
+// emoji whose name or shortcodes contain every word of the query, the ones
+// with a shortcode starting with the query first
+pub fn search(query: &str) -> Vec<&'static Emoji> {
+    let query = query.trim().trim_matches(':').to_lowercase();
+    let words: Vec<&str> = query.split_whitespace().collect();
+    if words.is_empty() {
+        return Vec::new();
+    }
+
+    let mut found: Vec<(bool, &'static Emoji)> = emojis::iter()
+        .filter(|emoji| {
+            let name = emoji.name().to_lowercase();
+            words.iter().all(|word| {
+                name.contains(word) || emoji.shortcodes().any(|code| code.contains(word))
+            })
+        })
+        .map(|emoji| (!emoji.shortcodes().any(|code| code.starts_with(&query)), emoji))
+        .collect();
+    // stable, so each half keeps the dataset's order
+    found.sort_by_key(|(later, _)| *later);
+    found.into_iter().map(|(_, emoji)| emoji).take(MAX_RESULTS).collect()
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '+' || c == '-'
+}
+
+// the emoji for `:code:`, with the colons already stripped
+fn lookup(code: &str) -> Option<&'static str> {
+    if code.is_empty() || !code.chars().all(is_shortcode_char) {
+        return None;
+    }
+    emojis::get_by_shortcode(code).map(|emoji| emoji.as_str())
+}
+
+// replaces every known :shortcode: with its emoji, unknown ones and those
+// inside `code` are left alone
+pub fn expand_shortcodes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    // splitting on backticks leaves code at the odd indices, fences included
+    for (i, part) in text.split('`').enumerate() {
+        if i > 0 {
+            out.push('`');
+        }
+        if i % 2 == 1 {
+            out.push_str(part);
+            continue;
+        }
+
+        let mut rest = part;
+        while let Some(start) = rest.find(':') {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            match after.find(':').and_then(|end| Some((end, lookup(&after[..end])?))) {
+                Some((end, emoji)) => {
+                    out.push_str(emoji);
+                    rest = &after[end + 1..];
+                },
+                // the closing colon may open the next shortcode
+                None => {
+                    out.push(':');
+                    rest = after;
+                },
+            }
+        }
+        out.push_str(rest);
+    }
+    out
+}
+
+// expands a shortcode that was just finished at the end of the composer
+// text, so `:thumbsup:` turns into 👍 as it's typed
+pub fn expand_at_end(text: &str) -> Option<String> {
+    let body = text.strip_suffix(':')?;
+    let start = body.rfind(':')?;
+    // inside an unclosed code span
+    if body.matches('`').count() % 2 == 1 {
+        return None;
+    }
+    let emoji = lookup(&body[start + 1..])?;
+    Some(format!("{}{emoji}", &body[..start]))
+}
+
+// the partially typed shortcode at the end of the composer text, as the byte
+// index of its colon and the text typed after it
+pub fn partial_at_end(text: &str) -> Option<(usize, &str)> {
+    let colon = text.rfind(':')?;
+    let prefix = &text[colon + 1..];
+    // a colon inside a word, like in a time or a url
+    let glued = text[..colon].chars().next_back().is_some_and(|c| !c.is_whitespace());
+    if prefix.len() < 2 || glued || !prefix.chars().all(is_shortcode_char) {
+        return None;
+    }
+    Some((colon, prefix))
+}
+
+// emoji with a shortcode starting with the prefix, for the composer's suggestions
+pub fn shortcode_suggestions(prefix: &str, limit: usize) -> Vec<(&'static str, &'static Emoji)> {
+    let prefix = prefix.to_lowercase();
+    emojis::iter()
+        .flat_map(|emoji| emoji.shortcodes().map(move |code| (code, emoji)))
+        .filter(|(code, _)| code.starts_with(&prefix))
+        .take(limit)
+        .collect()
+}
+
+// emoji picked lately, newest first, kept between runs
+#[derive(Default)]
+pub(crate) struct RecentEmoji {
+    pub(crate) emoji: Vec<String>,
+    path: PathBuf,
+}
+
+impl RecentEmoji {
+    pub fn load(path: PathBuf) -> Self {
+        let emoji = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                eprintln!("could not read {}: {e}", path.display());
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Self { emoji, path }
+    }
+
+    pub fn record(&mut self, emoji: &str) {
+        self.emoji.retain(|e| e != emoji);
+        self.emoji.insert(0, emoji.to_string());
+        self.emoji.truncate(MAX_RECENT);
+
+        let result = serde_json::to_vec(&self.emoji)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                }
+                std::fs::write(&self.path, json).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("could not save {}: {e}", self.path.display());
+        }
+    }
+}
+
+// custom emoji names are what goes between the colons
+pub fn is_custom_name(name: &str) -> bool {
+    (2..=32).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+// checks an emoji an admin sent before the server keeps it
+pub fn check_custom(emoji: &CustomEmoji) -> Result<(), String> {
+    if !is_custom_name(&emoji.name) {
+        return Err("emoji names are 2 to 32 lowercase letters, digits or underscores".to_string());
+    }
+    // it would be expanded to the unicode emoji before it was ever sent
+    if emojis::get_by_shortcode(&emoji.name).is_some() {
+        return Err(format!(":{}: is already an emoji", emoji.name));
+    }
+    if emoji.bytes.len() > MAX_CUSTOM_EMOJI_BYTES {
+        return Err(format!("the image is over {} KiB", MAX_CUSTOM_EMOJI_BYTES / 1024));
+    }
+    if content_hash(&emoji.bytes) != emoji.hash {
+        return Err("the image doesn't match its hash".to_string());
+    }
+    validate::check_image(&emoji.bytes)?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Piece<'a> {
+    Text(&'a str),
+    // a custom emoji's name, without the colons
+    Custom(&'a str),
+}
+
+// splits text around the :name:s that `known` recognises
+pub fn split_custom<'a>(text: &'a str, mut known: impl FnMut(&str) -> bool) -> Vec<Piece<'a>> {
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut search_from = 0;
+    while let Some(open) = text[search_from..].find(':').map(|i| i + search_from) {
+        let Some(close) = text[open + 1..].find(':').map(|i| i + open + 1) else {
+            break;
+        };
+        let name = &text[open + 1..close];
+        if is_custom_name(name) && known(name) {
+            if open > text_start {
+                pieces.push(Piece::Text(&text[text_start..open]));
+            }
+            pieces.push(Piece::Custom(name));
+            text_start = close + 1;
+            search_from = close + 1;
+        } else {
+            // the closing colon may open the next one
+            search_from = close;
+        }
+    }
+    if text_start < text.len() {
+        pieces.push(Piece::Text(&text[text_start..]));
+    }
+    pieces
+}
+
+pub(crate) struct CustomImage {
+    pub(crate) hash: String,
+    pub(crate) bytes: Arc<[u8]>,
+}
+
+// the custom emoji of the server we're connected to
+#[derive(Default)]
+pub(crate) struct ServerEmoji {
+    pub(crate) images: BTreeMap<String, CustomImage>,
+    pub(crate) can_add: bool,
+}
+
+impl ServerEmoji {
+    pub fn replace(&mut self, list: CustomEmojiList) {
+        self.images.clear();
+        self.can_add = list.can_add;
+        for emoji in list.emoji {
+            self.insert(emoji);
+        }
+    }
+
+    pub fn insert(&mut self, emoji: CustomEmoji) {
+        self.images.insert(emoji.name, CustomImage { hash: emoji.hash, bytes: emoji.bytes.into() });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CustomImage> {
+        self.images.get(name)
+    }
+
+    // names containing the query, or starting with it for the composer
+    pub fn search(&self, query: &str, prefix_only: bool) -> Vec<&str> {
+        let query = query.trim().trim_matches(':').to_lowercase();
+        self.images
+            .keys()
+            .filter(|name| if prefix_only { name.starts_with(&query) } else { name.contains(&query) })
+            .map(String::as_str)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum PickerTab {
+    Unicode(Group),
+    // the server's custom emoji
+    Server,
+}
+
+// what the emoji popup is showing
+pub(crate) struct EmojiPicker {
+    pub(crate) search: String,
+    pub(crate) tab: PickerTab,
+    pub(crate) skin_tone: SkinTone,
+    // name for the next custom emoji an admin adds
+    pub(crate) new_name: String,
+}
+
+impl Default for EmojiPicker {
+    fn default() -> Self {
+        Self {
+            search: String::new(),
+            tab: PickerTab::Unicode(Group::SmileysAndEmotion),
+            skin_tone: SkinTone::Default,
+            new_name: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TempDir, encode_image};
+
+    #[test]
+    fn shortcodes_expand() {
+        assert_eq!(expand_shortcodes("nice :thumbsup: :tada:"), "nice 👍 🎉");
+        assert_eq!(expand_shortcodes(":not_an_emoji: :smile:"), ":not_an_emoji: 😄");
+        assert_eq!(expand_shortcodes("at 10:30:15"), "at 10:30:15");
+        assert_eq!(expand_shortcodes("a::heart:"), "a:❤️");
+    }
+
+    #[test]
+    fn shortcodes_in_code_are_kept() {
+        assert_eq!(expand_shortcodes("`:smile:` :smile:"), "`:smile:` 😄");
+        assert_eq!(expand_shortcodes("```\n:smile:\n```"), "```\n:smile:\n```");
+    }
+
+    #[test]
+    fn shortcode_expands_when_finished() {
+        assert_eq!(expand_at_end("ok :thumbsup:").as_deref(), Some("ok 👍"));
+        assert_eq!(expand_at_end("ok :thumbsup"), None);
+        assert_eq!(expand_at_end("ok :nope:"), None);
+        assert_eq!(expand_at_end("`:smile:"), None);
+    }
+
+    #[test]
+    fn partial_shortcodes() {
+        assert_eq!(partial_at_end("hi :thu"), Some((3, "thu")));
+        assert_eq!(partial_at_end("hi :t"), None);
+        assert_eq!(partial_at_end("at 10:30"), None);
+        assert_eq!(partial_at_end("https://x"), None);
+        assert!(shortcode_suggestions("thumbsu", 5).iter().any(|(code, _)| *code == "thumbsup"));
+    }
+
+    #[test]
+    fn search_finds_by_name_and_shortcode() {
+        let found = search("thumbs");
+        assert_eq!(found.first().map(|e| e.as_str()), Some("👍"));
+        assert!(search("red heart").iter().any(|e| e.as_str() == "❤️"));
+        assert!(search("   ").is_empty());
+    }
+
+    #[test]
+    fn skin_tones_apply_where_they_exist() {
+        let wave = emojis::get("👋").unwrap();
+        assert_eq!(with_tone(wave, SkinTone::Dark).as_str(), "👋🏿");
+        let cool = emojis::get("😎").unwrap();
+        assert_eq!(with_tone(cool, SkinTone::Dark).as_str(), "😎");
+    }
+
+    #[test]
+    fn custom_emoji_are_split_out() {
+        let known = |name: &str| name == "party_parrot";
+        assert_eq!(
+            split_custom("yes :party_parrot: :nope: 10:party_parrot:", known),
+            [
+                Piece::Text("yes "),
+                Piece::Custom("party_parrot"),
+                Piece::Text(" :nope: 10"),
+                Piece::Custom("party_parrot"),
+            ]
+        );
+        assert_eq!(split_custom("no emoji", known), [Piece::Text("no emoji")]);
+    }
+
+    #[test]
+    fn custom_emoji_are_checked() {
+        let png = encode_image(8, 8, image::ImageFormat::Png);
+        let emoji = |name: &str, bytes: &[u8]| CustomEmoji {
+            name: name.to_string(),
+            hash: content_hash(bytes),
+            bytes: bytes.to_vec(),
+        };
+
+        assert!(check_custom(&emoji("team_logo", &png)).is_ok());
+        assert!(check_custom(&emoji("Team Logo", &png)).is_err());
+        assert!(check_custom(&emoji("thumbsup", &png)).is_err());
+        assert!(check_custom(&emoji("team_logo", b"not an image")).is_err());
+        let mut wrong_hash = emoji("team_logo", &png);
+        wrong_hash.hash = content_hash(b"other");
+        assert!(check_custom(&wrong_hash).is_err());
+    }
+
+    #[test]
+    fn recent_emoji_are_deduplicated_and_kept() {
+        let dir = TempDir::new("recent-emoji");
+        let path = dir.join("recent_emoji.json");
+        let mut recent = RecentEmoji::load(path.clone());
+        for i in 0..MAX_RECENT + 3 {
+            recent.record(&i.to_string());
+        }
+        recent.record("5");
+        assert_eq!(recent.emoji.len(), MAX_RECENT);
+        assert_eq!(recent.emoji[0], "5");
+        assert_eq!(recent.emoji.iter().filter(|e| *e == "5").count(), 1);
+
+        assert_eq!(RecentEmoji::load(path).emoji, recent.emoji);
+    }
+}
@@ -0,0 +1,101 @@
+// files kept between runs so the gif popup opens without waiting on the
+// network. entries older than the cache's lifetime are treated as missing
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::hash::content_hash;
+
+#[derive(Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+// $XDG_CACHE_HOME/rust-chat, ~/.cache/rust-chat or the temp dir
+pub fn default_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("rust-chat")
+}
+
+impl DiskCache {
+    pub fn new(dir: PathBuf, max_age: Duration) -> Self {
+        Self { dir, max_age }
+    }
+
+    // keys can be anything, like a url, so files are named by their hash
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(content_hash(key.as_bytes()))
+    }
+
+    fn is_fresh(&self, modified: SystemTime) -> bool {
+        SystemTime::now()
+            .duration_since(modified)
+            .map_or(true, |age| age < self.max_age)
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key);
+        let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+        if !self.is_fresh(modified) {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        std::fs::read(&path).ok()
+    }
+
+    // failing to cache only costs a download next time
+    pub fn put(&self, key: &str, bytes: &[u8]) {
+        let path = self.path(key);
+        if let Err(e) = std::fs::create_dir_all(&self.dir).and_then(|_| std::fs::write(&path, bytes)) {
+            eprintln!("could not cache {}: {e}", path.display());
+        }
+    }
+
+    // removes expired entries
+    pub fn prune(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let expired = entry.metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| !self.is_fresh(modified));
+            if expired {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn entries_are_kept_until_they_expire() {
+        let dir = TempDir::new("cache-fresh");
+        let cache = DiskCache::new(dir.join("cache"), Duration::from_secs(60));
+        assert_eq!(cache.get("https://x/a.gif"), None);
+        cache.put("https://x/a.gif", b"gif");
+        assert_eq!(cache.get("https://x/a.gif").as_deref(), Some(&b"gif"[..]));
+
+        let expired = DiskCache::new(dir.join("cache"), Duration::ZERO);
+        assert_eq!(expired.get("https://x/a.gif"), None);
+        // and it was removed
+        assert_eq!(cache.get("https://x/a.gif"), None);
+    }
+
+    #[test]
+    fn prune_removes_expired_files() {
+        let dir = TempDir::new("cache-prune");
+        DiskCache::new(dir.path().to_path_buf(), Duration::from_secs(60)).put("a", b"1");
+        DiskCache::new(dir.path().to_path_buf(), Duration::ZERO).prune();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,203 @@
+// gifs from a folder on disk, for LANs without internet access. each
+// subfolder is a category and searches match the file path
+use std::path::{Component, Path, PathBuf};
+
+use crate::gif::provider::{Category, GifInfo, GifProvider, MediaSize, ProviderFuture, ProviderPage};
+
+const MAX_SUGGESTIONS: usize = 8;
+
+pub struct LocalGifs {
+    dir: PathBuf,
+}
+
+impl LocalGifs {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    // every gif under the folder, ids are paths relative to it
+    fn list(&self) -> Vec<GifInfo> {
+        let mut ids = Vec::new();
+        collect(&self.dir, &self.dir, &mut ids);
+        ids.sort();
+        ids.into_iter()
+            .map(|id| {
+                let url = self.dir.join(&id).display().to_string();
+                GifInfo { id, preview_url: url.clone(), url }
+            })
+            .collect()
+    }
+
+    // the file behind an id, None for ids pointing outside the folder
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let relative = Path::new(id);
+        relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            .then(|| self.dir.join(relative))
+    }
+}
+
+fn collect(root: &Path, dir: &Path, out: &mut Vec<String>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("could not read {}: {e}", dir.display());
+            return;
+        },
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(root, &path, out);
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif"))
+            && let Ok(relative) = path.strip_prefix(root)
+        {
+            let id = relative.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            out.push(id);
+        }
+    }
+}
+
+// a page of `gifs` starting at the offset held in `pos`
+fn page(gifs: Vec<GifInfo>, pos: Option<&str>, limit: u32) -> ProviderPage {
+    let start = pos.and_then(|pos| pos.parse().ok()).unwrap_or(0).min(gifs.len());
+    let end = (start + limit as usize).min(gifs.len());
+    ProviderPage {
+        next: (end < gifs.len()).then(|| end.to_string()),
+        gifs: gifs[start..end].to_vec(),
+    }
+}
+
+impl GifProvider for LocalGifs {
+    fn name(&self) -> &str {
+        "local folder"
+    }
+
+    fn featured<'a>(&'a self, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        Box::pin(async move { Ok(page(self.list(), pos, limit)) })
+    }
+
+    fn search<'a>(&'a self, query: &'a str, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        Box::pin(async move {
+            let query = query.to_lowercase();
+            let words: Vec<&str> = query.split_whitespace().collect();
+            let found = self.list()
+                .into_iter()
+                .filter(|gif| {
+                    let id = gif.id.to_lowercase();
+                    words.iter().all(|word| id.contains(word))
+                })
+                .collect();
+            Ok(page(found, pos, limit))
+        })
+    }
+
+    // the first gif in each folder goes on its tile
+    fn categories(&self) -> ProviderFuture<'_, Vec<Category>> {
+        Box::pin(async move {
+            let entries = std::fs::read_dir(&self.dir).map_err(|e| e.to_string())?;
+            let gifs = self.list();
+            let mut categories: Vec<Category> = entries
+                .flatten()
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let prefix = format!("{name}/");
+                    let image = gifs.iter().find(|gif| gif.id.starts_with(&prefix)).cloned();
+                    Category { query: name.clone(), name, image }
+                })
+                .collect();
+            categories.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(categories)
+        })
+    }
+
+    // words from file names starting with the last word typed
+    fn autocomplete<'a>(&'a self, prefix: &'a str) -> ProviderFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let prefix = prefix.to_lowercase();
+            let (typed, last) = prefix.rsplit_once(' ').unwrap_or(("", &prefix));
+            let mut words = Vec::new();
+            for gif in self.list() {
+                let stem = gif.id.to_lowercase().trim_end_matches(".gif").to_string();
+                for word in stem.split(|c: char| !c.is_alphanumeric()) {
+                    if word.starts_with(last) && word != last && !words.contains(&word.to_string()) {
+                        words.push(word.to_string());
+                    }
+                }
+            }
+            words.sort();
+            words.truncate(MAX_SUGGESTIONS);
+            Ok(words.into_iter()
+                .map(|word| if typed.is_empty() { word } else { format!("{typed} {word}") })
+                .collect())
+        })
+    }
+
+    // there is only the one file, whichever size is asked for
+    fn download<'a>(&'a self, gif: &'a GifInfo, _size: MediaSize) -> ProviderFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            let path = self.path(&gif.id).ok_or_else(|| format!("bad gif id {}", gif.id))?;
+            std::fs::read(&path).map_err(|e| format!("could not read {}: {e}", path.display()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    // a temp folder holding the files, each containing its own name
+    fn folder(name: &str, files: &[&str]) -> TempDir {
+        let dir = TempDir::new(&format!("gifs-{name}"));
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, file.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    #[tokio::test]
+    async fn lists_pages_and_categories() {
+        let dir = folder("list", &["b.gif", "a.GIF", "notes.txt", "cats/sleepy cat.gif"]);
+        let gifs = LocalGifs::new(dir.path().to_path_buf());
+
+        let first = gifs.featured(None, 2).await.unwrap();
+        let ids: Vec<_> = first.gifs.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a.GIF", "b.gif"]);
+
+        let second = gifs.featured(first.next.as_deref(), 2).await.unwrap();
+        assert_eq!(second.gifs[0].id, "cats/sleepy cat.gif");
+        assert_eq!(second.next, None);
+
+        let categories = gifs.categories().await.unwrap();
+        assert_eq!(categories[0].name, "cats");
+        assert_eq!(categories[0].image.as_ref().map(|g| g.id.as_str()), Some("cats/sleepy cat.gif"));
+        assert_eq!(gifs.autocomplete("cats sl").await.unwrap(), ["cats sleepy"]);
+        let found = gifs.search("CATS sleepy", None, 10).await.unwrap();
+        assert_eq!(found.gifs.len(), 1);
+
+        let bytes = gifs.download(&second.gifs[0], MediaSize::Full).await.unwrap();
+        assert_eq!(bytes, b"cats/sleepy cat.gif");
+    }
+
+    #[tokio::test]
+    async fn ids_cannot_leave_the_folder() {
+        let dir = folder("escape", &["a.gif"]);
+        let gifs = LocalGifs::new(dir.path().to_path_buf());
+        for id in ["../escape.gif", "/etc/passwd"] {
+            let outside = GifInfo {
+                id: id.to_string(),
+                url: String::new(),
+                preview_url: String::new(),
+            };
+            assert!(gifs.download(&outside, MediaSize::Preview).await.is_err());
+        }
+    }
+}
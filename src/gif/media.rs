@@ -0,0 +1,147 @@
+// gifs sent by reference, each client downloads them from the provider's urls
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+
+use crate::{
+    attachment::{MAX_ATTACHMENT_BYTES, validate},
+    gif::GifRef,
+    hash::content_hash,
+    link_preview::read_limited,
+    ui::texture_cache::TextureCache,
+};
+
+enum MediaState {
+    Loading,
+    Ready { hash: String, bytes: Arc<[u8]> },
+    Failed,
+}
+
+// client side cache of gif media, keyed by url
+pub(crate) struct GifMedia {
+    client: reqwest::Client,
+    cache: HashMap<String, MediaState>,
+    tx: Sender<(String, Result<Vec<u8>, String>)>,
+    rx: Receiver<(String, Result<Vec<u8>, String>)>,
+}
+
+impl Default for GifMedia {
+    fn default() -> Self {
+        let (tx, rx) = channel(32);
+        Self {
+            client: reqwest::Client::new(),
+            cache: HashMap::new(),
+            tx,
+            rx,
+        }
+    }
+}
+
+// downloads an image from the web, refusing anything too large to show safely
+pub async fn fetch(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported scheme: {}", url.scheme()));
+    }
+
+    let response = client.get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let limit = MAX_ATTACHMENT_BYTES as usize;
+    let bytes = read_limited(response, limit + 1).await.map_err(|e| e.to_string())?;
+    if bytes.len() > limit {
+        return Err("the gif is too large".to_string());
+    }
+    validate::check_image(&bytes)?;
+    Ok(bytes)
+}
+
+impl GifMedia {
+    pub fn client(&self) -> reqwest::Client {
+        self.client.clone()
+    }
+
+    // collects finished downloads
+    pub fn poll(&mut self) {
+        while let Ok((url, result)) = self.rx.try_recv() {
+            let state = match result {
+                Ok(bytes) => MediaState::Ready { hash: content_hash(&bytes), bytes: bytes.into() },
+                Err(e) => {
+                    eprintln!("couldn't download gif {url}: {e}");
+                    MediaState::Failed
+                },
+            };
+            self.cache.insert(url, state);
+        }
+    }
+
+    fn request(&mut self, url: &str, rt: &tokio::runtime::Handle) {
+        let client = self.client.clone();
+        let tx = self.tx.clone();
+        let url = url.to_string();
+        self.cache.insert(url.clone(), MediaState::Loading);
+        rt.spawn(async move {
+            let result = fetch(&client, &url).await;
+            let _ = tx.send((url, result)).await;
+        });
+    }
+
+    // shows a gif message, downloading it once it scrolls into view
+    pub fn show(&mut self, ui: &mut egui::Ui, gif: &GifRef, textures: &mut TextureCache, rt: &tokio::runtime::Handle) {
+        // the server drops these too, but it might not be ours
+        if !gif.is_valid() {
+            ui.hyperlink_to(format!("GIF from {}", gif.provider), &gif.url).on_hover_text(&gif.url);
+            return;
+        }
+        let size = egui::vec2(250.0, 250.0);
+        match self.cache.get(&gif.url) {
+            Some(MediaState::Ready { hash, bytes }) => {
+                if let Some(texture) = textures.get(ui.ctx(), hash, || Some(Arc::clone(bytes))) {
+                    ui.add(egui::Image::from_texture(texture).max_size(size).corner_radius(5));
+                    return;
+                }
+                link(ui, gif);
+            },
+            Some(MediaState::Failed) => link(ui, gif),
+            Some(MediaState::Loading) | None => {
+                let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                if ui.is_rect_visible(rect) {
+                    egui::Spinner::new().paint_at(ui, egui::Rect::from_center_size(rect.center(), egui::vec2(24.0, 24.0)));
+                    if !self.cache.contains_key(&gif.url) {
+                        self.request(&gif.url, rt);
+                    }
+                }
+            },
+        }
+    }
+}
+
+// shown in place of a gif that couldn't be loaded
+fn link(ui: &mut egui::Ui, gif: &GifRef) {
+    ui.hyperlink_to(format!("GIF from {} (couldn't be loaded)", gif.provider), &gif.url);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{StubResponse, encode_image, serve};
+
+    fn tiny_gif() -> Vec<u8> {
+        encode_image(2, 2, image::ImageFormat::Gif)
+    }
+
+    #[tokio::test]
+    async fn only_images_are_accepted() {
+        let (addr, _) = serve(vec![
+            ("/cat.gif", StubResponse::ok("image/gif", tiny_gif())),
+            ("/page", StubResponse::ok("text/html", "<html></html>")),
+        ]).await;
+        let client = reqwest::Client::new();
+
+        assert_eq!(fetch(&client, &format!("http://{addr}/cat.gif")).await.unwrap(), tiny_gif());
+        assert!(fetch(&client, &format!("http://{addr}/page")).await.is_err());
+        assert!(fetch(&client, &format!("http://{addr}/missing.gif")).await.is_err());
+        assert!(fetch(&client, "file:///etc/passwd").await.is_err());
+    }
+}
@@ -0,0 +1,447 @@
+pub mod disk_cache;
+pub mod local;
+pub mod media;
+pub mod provider;
+pub mod saved;
+
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use crate::{gif::provider::{Category, GifInfo, GifProvider, MediaSize}, hash::content_hash};
+
+// how many gifs the popup asks for at a time
+pub const PAGE_SIZE: u32 = 20;
+// previews downloading at once, over every page and category tile
+const MAX_CONCURRENT_DOWNLOADS: usize = 6;
+// the hosts each provider that can share gifs by reference serves its media
+// from. gifs sent by reference are only downloaded from these
+const MEDIA_HOSTS: &[(&str, &[&str])] = &[("Tenor", &["media.tenor.com", "media1.tenor.com", "c.tenor.com"])];
+
+static DOWNLOAD_SLOTS: Semaphore = Semaphore::const_new(MAX_CONCURRENT_DOWNLOADS);
+
+#[derive(Clone)]
+pub struct Gif {
+    pub(crate) id: String,
+    pub(crate) url: String,
+    pub(crate) preview_url: String,
+    // content hash of preview_bytes, the key for its texture
+    pub(crate) hash: String,
+    // the small version shown while browsing
+    pub(crate) preview_bytes: Vec<u8>,
+}
+
+// a gif sent by reference, each recipient downloads it from the provider
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GifRef {
+    pub provider: String,
+    pub id: String,
+    pub url: String,
+    pub preview_url: String,
+}
+
+impl GifRef {
+    pub fn new(provider: &str, gif: &Gif) -> Self {
+        Self {
+            provider: provider.to_string(),
+            id: gif.id.clone(),
+            url: gif.url.clone(),
+            preview_url: gif.preview_url.clone(),
+        }
+    }
+
+    // true when both urls are on the named provider's media hosts. anything
+    // else could point a client at any server, including ones on its own network
+    pub fn is_valid(&self) -> bool {
+        let Some((_, hosts)) = MEDIA_HOSTS.iter().find(|(provider, _)| *provider == self.provider) else {
+            return false;
+        };
+        [&self.url, &self.preview_url].iter().all(|url| {
+            reqwest::Url::parse(url).is_ok_and(|url| {
+                url.scheme() == "https" && url.port().is_none() && url.host_str().is_some_and(|host| hosts.contains(&host))
+            })
+        })
+    }
+}
+
+// one page of results on its way back from the api
+pub struct GifPage {
+    // the search it belongs to, empty for featured gifs
+    pub(crate) query: String,
+    pub(crate) gifs: Vec<Gif>,
+    pub(crate) next: Option<String>,
+    // gifs on the page whose preview couldn't be downloaded
+    pub(crate) failed: usize,
+    // why the page couldn't be loaded
+    pub(crate) error: Option<String>,
+}
+
+// how long typing has to pause before suggestions are asked for
+pub const AUTOCOMPLETE_DELAY_SECS: f64 = 0.3;
+
+// what the gif tasks send back to the popup
+pub enum GifEvent {
+    Page(GifPage),
+    // the categories, or why they couldn't be loaded
+    Categories(Result<Vec<Category>, String>),
+    // the gif for the tile of the category searching for `query`
+    CategoryImage { query: String, gif: Gif },
+    Trending(Vec<String>),
+    Suggestions { prefix: String, terms: Vec<String> },
+    // a picked gif downloaded to be sent as a file
+    Inline { name: String, bytes: Result<Vec<u8>, String> },
+}
+
+// a file name for a gif id, which can be a path for local gifs
+pub fn file_name(id: &str) -> String {
+    let name = id.rsplit('/').next().unwrap_or(id);
+    if name.to_lowercase().ends_with(".gif") {
+        name.to_string()
+    } else {
+        format!("{name}.gif")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub(crate) enum GifTab {
+    #[default]
+    Browse,
+    Favourites,
+    Recent,
+}
+
+impl GifTab {
+    pub const ALL: [GifTab; 3] = [GifTab::Browse, GifTab::Favourites, GifTab::Recent];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            GifTab::Browse => "browse",
+            GifTab::Favourites => "★ favourites",
+            GifTab::Recent => "recent",
+        }
+    }
+}
+
+// what the popup offers besides the gifs themselves
+#[derive(Default)]
+pub(crate) struct GifBrowse {
+    pub(crate) tab: GifTab,
+    // None until the provider has been asked for them, and again after
+    // asking failed so the next time the popup opens tries again
+    pub(crate) categories: Option<Vec<Category>>,
+    // tile images by category query
+    pub(crate) category_images: HashMap<String, Gif>,
+    pub(crate) trending: Vec<String>,
+    // suggestions and the text they complete
+    pub(crate) suggestions: (String, Vec<String>),
+    pub(crate) autocomplete: Debounce,
+}
+
+// holds back a value that keeps changing until it has been left alone for a
+// while, so autocomplete isn't asked about every key press
+#[derive(Default)]
+pub(crate) struct Debounce {
+    // the value and when it last changed
+    pending: Option<(String, f64)>,
+    last: String,
+}
+
+impl Debounce {
+    pub fn update(&mut self, value: &str, now: f64) {
+        match &self.pending {
+            Some((pending, _)) if pending == value => {},
+            _ if self.pending.is_none() && value == self.last => {},
+            _ => self.pending = Some((value.to_string(), now)),
+        }
+    }
+
+    // the value once it has settled, given out once
+    pub fn settled(&mut self, now: f64, delay: f64) -> Option<String> {
+        let (value, at) = self.pending.as_ref()?;
+        if now - at < delay {
+            return None;
+        }
+        let value = value.clone();
+        self.pending = None;
+        self.last = value.clone();
+        Some(value)
+    }
+
+    // true while a value is waiting to settle
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+}
+
+// downloads the preview of a gif for the grid, waiting for a free slot
+pub async fn download_preview(provider: &dyn GifProvider, info: GifInfo) -> Result<Gif, String> {
+    let _slot = DOWNLOAD_SLOTS.acquire().await.map_err(|e| e.to_string())?;
+    let bytes = provider.download(&info, MediaSize::Preview).await?;
+    Ok(Gif {
+        id: info.id,
+        url: info.url,
+        preview_url: info.preview_url,
+        hash: content_hash(&bytes),
+        preview_bytes: bytes,
+    })
+}
+
+// downloads previews side by side, the results are in the order of `infos`
+pub async fn download_previews(provider: Arc<dyn GifProvider>, infos: Vec<GifInfo>) -> Vec<Result<Gif, String>> {
+    let mut tasks = JoinSet::new();
+    for (i, info) in infos.into_iter().enumerate() {
+        let provider = Arc::clone(&provider);
+        tasks.spawn(async move {
+            let id = info.id.clone();
+            let result = download_preview(provider.as_ref(), info).await;
+            (i, result.map_err(|e| format!("couldn't download gif {id}: {e}")))
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok(result) => results.push(result),
+            Err(e) => eprintln!("gif download task failed: {e}"),
+        }
+    }
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+// loads a page of `query` (featured gifs when it is empty) and downloads the
+// previews of its gifs. gifs that fail to download are left out and counted
+pub async fn load_page(provider: Arc<dyn GifProvider>, query: String, pos: Option<String>, limit: u32) -> GifPage {
+    let resp = if query.is_empty() {
+        provider.featured(pos.as_deref(), limit).await
+    } else {
+        provider.search(&query, pos.as_deref(), limit).await
+    };
+    let page = match resp {
+        Ok(page) => page,
+        Err(e) => {
+            eprintln!("{}", e);
+            return GifPage {
+                query,
+                gifs: Vec::new(),
+                next: None,
+                failed: 0,
+                error: Some(format!("couldn't load gifs: {e}")),
+            };
+        },
+    };
+
+    let mut gifs = Vec::new();
+    let mut failed = 0;
+    for result in download_previews(provider, page.gifs).await {
+        match result {
+            Ok(gif) => gifs.push(gif),
+            Err(e) => {
+                eprintln!("{e}");
+                failed += 1;
+            },
+        }
+    }
+    GifPage { query, gifs, next: page.next, failed, error: None }
+}
+
+// the gifs shown in the popup, grown a page at a time as the user scrolls
+#[derive(Default)]
+pub(crate) struct GifFeed {
+    // the search being shown, empty for featured gifs
+    pub(crate) query: String,
+    pub(crate) gifs: Vec<Gif>,
+    next: Option<String>,
+    loading: bool,
+    // the api said there are no more pages
+    done: bool,
+    // why the last page failed, shown with a button to try again
+    pub(crate) error: Option<String>,
+    // gifs left out because their preview didn't download
+    pub(crate) failed: usize,
+}
+
+impl GifFeed {
+    // switches to another search, dropping what was loaded
+    pub fn reset(&mut self, query: String) {
+        *self = Self { query, ..Default::default() };
+    }
+
+    // the cursor to load next, None if a page is on its way or there are no
+    // more. marks the feed as loading
+    pub fn start_page(&mut self) -> Option<Option<String>> {
+        if self.loading || self.done {
+            return None;
+        }
+        self.loading = true;
+        Some(self.next.clone())
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.loading
+    }
+
+    // appends a page, skipping gifs already in the grid. pages for an older
+    // search are ignored
+    pub fn append(&mut self, page: GifPage) {
+        if page.query != self.query {
+            return;
+        }
+        self.loading = false;
+        self.error = page.error;
+        if self.error.is_some() {
+            return;
+        }
+        self.failed += page.failed;
+        for gif in page.gifs {
+            if !self.gifs.iter().any(|g| g.id == gif.id) {
+                self.gifs.push(gif);
+            }
+        }
+        self.done = page.next.is_none();
+        self.next = page.next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gif::provider::MockProvider;
+
+    fn gif(id: &str) -> Gif {
+        Gif {
+            id: id.to_string(),
+            url: String::new(),
+            preview_url: String::new(),
+            hash: String::new(),
+            preview_bytes: Vec::new(),
+        }
+    }
+
+    fn page(query: &str, ids: &[&str], next: Option<&str>) -> GifPage {
+        GifPage {
+            query: query.to_string(),
+            gifs: ids.iter().map(|id| gif(id)).collect(),
+            next: next.map(str::to_string),
+            failed: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn pages_are_appended_with_their_cursor() {
+        let mut feed = GifFeed::default();
+        assert_eq!(feed.start_page(), Some(None));
+        // one page at a time
+        assert_eq!(feed.start_page(), None);
+
+        feed.append(page("", &["a", "b"], Some("2")));
+        assert_eq!(feed.start_page(), Some(Some("2".to_string())));
+        feed.append(page("", &["b", "c"], None));
+
+        let ids: Vec<_> = feed.gifs.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        // the last page had no cursor
+        assert_eq!(feed.start_page(), None);
+    }
+
+    #[test]
+    fn pages_for_an_old_search_are_dropped() {
+        let mut feed = GifFeed::default();
+        feed.start_page();
+        feed.reset("cats".to_string());
+        feed.start_page();
+
+        feed.append(page("", &["a"], Some("2")));
+        assert!(feed.gifs.is_empty());
+        assert!(feed.is_loading());
+
+        feed.append(page("cats", &["cat"], Some("2")));
+        assert_eq!(feed.gifs.len(), 1);
+        assert!(!feed.is_loading());
+    }
+
+    #[test]
+    fn failed_pages_can_be_retried() {
+        let mut feed = GifFeed::default();
+        feed.start_page();
+        feed.append(GifPage {
+            error: Some("offline".to_string()),
+            ..page("", &[], None)
+        });
+        assert!(feed.error.is_some());
+        assert_eq!(feed.start_page(), Some(None));
+    }
+
+    #[test]
+    fn gif_refs_only_point_at_the_providers_media_hosts() {
+        let valid = GifRef::new("Tenor", &Gif {
+            url: "https://media.tenor.com/x/cat.gif".to_string(),
+            preview_url: "https://media.tenor.com/x/cat-tiny.gif".to_string(),
+            ..gif("x")
+        });
+        assert!(valid.is_valid());
+
+        for preview_url in [
+            "file:///home/me/secret.gif",
+            "http://media.tenor.com/x/cat-tiny.gif",
+            "https://media.tenor.com:8443/x/cat-tiny.gif",
+            "https://media.tenor.com.example.org/x/cat-tiny.gif",
+            "https://192.168.1.1/router.gif",
+        ] {
+            let gif = GifRef { preview_url: preview_url.to_string(), ..valid.clone() };
+            assert!(!gif.is_valid(), "{preview_url}");
+        }
+        // each provider only vouches for its own hosts
+        let gif = GifRef { provider: "local folder".to_string(), ..valid.clone() };
+        assert!(!gif.is_valid());
+    }
+
+    #[test]
+    fn file_names_for_ids() {
+        assert_eq!(file_name("12345"), "12345.gif");
+        assert_eq!(file_name("cats/sleepy cat.GIF"), "sleepy cat.GIF");
+    }
+
+    #[test]
+    fn debounce_waits_for_typing_to_pause() {
+        let mut debounce = Debounce::default();
+        debounce.update("c", 0.0);
+        debounce.update("ca", 0.1);
+        assert_eq!(debounce.settled(0.3, 0.3), None);
+        assert_eq!(debounce.settled(0.4, 0.3).as_deref(), Some("ca"));
+        // given out once, and not again for the same value
+        assert_eq!(debounce.settled(1.0, 0.3), None);
+        debounce.update("ca", 1.0);
+        assert!(!debounce.is_pending());
+        debounce.update("cat", 1.0);
+        assert!(debounce.is_pending());
+    }
+
+    #[tokio::test]
+    async fn pages_are_loaded_from_the_provider() {
+        let mut mock = MockProvider::new(&["cat", "dog", "cow"]);
+        mock.broken.push("dog".to_string());
+        let provider: Arc<dyn GifProvider> = Arc::new(mock);
+
+        let mut feed = GifFeed::default();
+        let pos = feed.start_page().unwrap();
+        feed.append(load_page(provider.clone(), String::new(), pos, 2).await);
+        // dog failed to download
+        let ids: Vec<_> = feed.gifs.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["cat"]);
+        assert_eq!(feed.failed, 1);
+
+        let pos = feed.start_page().unwrap();
+        feed.append(load_page(provider.clone(), String::new(), pos, 2).await);
+        assert_eq!(feed.gifs.len(), 2);
+        assert_eq!(feed.gifs[1].preview_bytes, b"cow preview");
+        assert_eq!(feed.start_page(), None);
+
+        feed.reset("c".to_string());
+        let pos = feed.start_page().unwrap();
+        feed.append(load_page(provider.clone(), "c".to_string(), pos, 2).await);
+        assert_eq!(feed.gifs.len(), 2);
+    }
+}
@@ -0,0 +1,163 @@
+// where the gif popup gets its gifs from
+use std::{future::Future, path::PathBuf, pin::Pin, sync::Arc};
+
+use crate::{gif::{disk_cache, local::LocalGifs}, tenor::TenorAPI};
+
+pub type ProviderFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+// a gif as a provider lists it, before its bytes are downloaded
+#[derive(Clone, Debug, PartialEq)]
+pub struct GifInfo {
+    pub id: String,
+    pub url: String,
+    // a smaller version for the grid
+    pub preview_url: String,
+}
+
+// which version of a gif to download
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MediaSize {
+    Preview,
+    Full,
+}
+
+pub struct ProviderPage {
+    pub gifs: Vec<GifInfo>,
+    // cursor for the page after this one, None on the last page
+    pub next: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Category {
+    pub name: String,
+    // what to search for to show the category
+    pub query: String,
+    // a gif to show on the category's tile
+    pub image: Option<GifInfo>,
+}
+
+pub trait GifProvider: Send + Sync {
+    // shown in the popup
+    fn name(&self) -> &str;
+
+    // true when other clients can download the provider's urls themselves,
+    // so gifs can be sent as a reference instead of their bytes
+    fn shareable(&self) -> bool {
+        false
+    }
+
+    // `pos` is the cursor from the previous page
+    fn featured<'a>(&'a self, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage>;
+
+    fn search<'a>(&'a self, query: &'a str, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage>;
+
+    fn categories(&self) -> ProviderFuture<'_, Vec<Category>>;
+
+    // popular searches, shown before anything is typed
+    fn trending(&self) -> ProviderFuture<'_, Vec<String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    // searches that complete what has been typed so far
+    fn autocomplete<'a>(&'a self, prefix: &'a str) -> ProviderFuture<'a, Vec<String>> {
+        let _ = prefix;
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn download<'a>(&'a self, gif: &'a GifInfo, size: MediaSize) -> ProviderFuture<'a, Vec<u8>>;
+}
+
+// tenor when there is an api key, a folder of gifs when GIF_DIR is set,
+// otherwise the popup goes without
+pub fn from_env() -> Option<Arc<dyn GifProvider>> {
+    if let Ok(key) = std::env::var("TENOR_API_KEY") {
+        // TENOR_BASE_URL points the client at a proxy or a mirror
+        if let Ok(base) = std::env::var("TENOR_BASE_URL") {
+            match reqwest::Url::parse(&base) {
+                Ok(base) => return Some(Arc::new(TenorAPI::with_base_url(key, base).with_cache(cache_dir()))),
+                Err(e) => eprintln!("bad TENOR_BASE_URL {base}: {e}"),
+            }
+        }
+        return Some(Arc::new(TenorAPI::new(key).with_cache(cache_dir())));
+    }
+    if let Ok(dir) = std::env::var("GIF_DIR") {
+        let dir = PathBuf::from(dir);
+        if dir.is_dir() {
+            return Some(Arc::new(LocalGifs::new(dir)));
+        }
+        eprintln!("GIF_DIR {} is not a directory", dir.display());
+    }
+    None
+}
+
+fn cache_dir() -> PathBuf {
+    disk_cache::default_dir().join("tenor")
+}
+
+// pages cut from a fixed list, for tests
+#[cfg(test)]
+pub struct MockProvider {
+    pub gifs: Vec<GifInfo>,
+    pub categories: Vec<Category>,
+    // ids whose download fails
+    pub broken: Vec<String>,
+}
+
+#[cfg(test)]
+impl MockProvider {
+    pub fn new(ids: &[&str]) -> Self {
+        Self {
+            gifs: ids
+                .iter()
+                .map(|id| GifInfo {
+                    id: id.to_string(),
+                    url: format!("mock://{id}"),
+                    preview_url: format!("mock://{id}/preview"),
+                })
+                .collect(),
+            categories: Vec::new(),
+            broken: Vec::new(),
+        }
+    }
+
+    fn page(&self, gifs: Vec<GifInfo>, pos: Option<&str>, limit: u32) -> ProviderPage {
+        let start = pos.and_then(|pos| pos.parse().ok()).unwrap_or(0);
+        let end = (start + limit as usize).min(gifs.len());
+        ProviderPage {
+            gifs: gifs.get(start..end).unwrap_or_default().to_vec(),
+            next: (end < gifs.len()).then(|| end.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl GifProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    fn featured<'a>(&'a self, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        Box::pin(async move { Ok(self.page(self.gifs.clone(), pos, limit)) })
+    }
+
+    fn search<'a>(&'a self, query: &'a str, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        let found = self.gifs.iter().filter(|g| g.id.contains(query)).cloned().collect();
+        Box::pin(async move { Ok(self.page(found, pos, limit)) })
+    }
+
+    fn categories(&self) -> ProviderFuture<'_, Vec<Category>> {
+        Box::pin(async move { Ok(self.categories.clone()) })
+    }
+
+    fn download<'a>(&'a self, gif: &'a GifInfo, size: MediaSize) -> ProviderFuture<'a, Vec<u8>> {
+        Box::pin(async move {
+            if self.broken.contains(&gif.id) {
+                return Err(format!("{} is broken", gif.id));
+            }
+            Ok(match size {
+                MediaSize::Preview => format!("{} preview", gif.id).into_bytes(),
+                MediaSize::Full => gif.id.as_bytes().to_vec(),
+            })
+        })
+    }
+}
@@ -0,0 +1,172 @@
+// favourite and recently sent gifs, kept on disk with their previews so they
+// can be browsed and sent while the provider is unreachable
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{app::app::data_dir, gif::GifRef};
+
+// how many recently sent gifs are remembered
+const MAX_RECENT: usize = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedGif {
+    pub gif: GifRef,
+    // whether the provider's urls can be sent by reference
+    pub shareable: bool,
+    // content hash of the preview, stored next to the list
+    pub preview_hash: String,
+}
+
+impl SavedGif {
+    fn same(&self, other: &SavedGif) -> bool {
+        self.gif.provider == other.gif.provider && self.gif.id == other.gif.id
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub(crate) struct SavedGifs {
+    pub(crate) favourites: Vec<SavedGif>,
+    // newest first
+    pub(crate) recent: Vec<SavedGif>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+pub fn default_dir() -> PathBuf {
+    data_dir().join("gifs")
+}
+
+impl SavedGifs {
+    // reads the lists from `dir`, starting empty if there are none yet
+    pub fn load(dir: PathBuf) -> Self {
+        let path = dir.join("saved.json");
+        let mut saved = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice::<SavedGifs>(&bytes).unwrap_or_else(|e| {
+                eprintln!("could not read {}: {e}", path.display());
+                SavedGifs::default()
+            }),
+            Err(_) => SavedGifs::default(),
+        };
+        saved.dir = dir;
+        saved
+    }
+
+    fn save(&self) {
+        let path = self.dir.join("saved.json");
+        let result = serde_json::to_vec_pretty(self)
+            .map_err(|e| e.to_string())
+            .and_then(|json| {
+                std::fs::create_dir_all(&self.dir)
+                    .and_then(|_| std::fs::write(&path, json))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("could not save {}: {e}", path.display());
+        }
+    }
+
+    fn preview_path(&self, hash: &str) -> PathBuf {
+        self.dir.join("previews").join(hash)
+    }
+
+    pub fn preview(&self, hash: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.preview_path(hash)).ok()
+    }
+
+    // previews are named by their hash, so one written before is kept
+    fn store_preview(&self, hash: &str, bytes: &[u8]) {
+        let path = self.preview_path(hash);
+        if path.exists() {
+            return;
+        }
+        let result = std::fs::create_dir_all(self.dir.join("previews")).and_then(|_| std::fs::write(&path, bytes));
+        if let Err(e) = result {
+            eprintln!("could not save gif preview {}: {e}", path.display());
+        }
+    }
+
+    pub fn is_favourite(&self, gif: &GifRef) -> bool {
+        self.favourites.iter().any(|f| f.gif.provider == gif.provider && f.gif.id == gif.id)
+    }
+
+    // stars or unstars a gif
+    pub fn toggle_favourite(&mut self, saved: SavedGif, preview: Option<&[u8]>) {
+        if let Some(i) = self.favourites.iter().position(|f| f.same(&saved)) {
+            self.favourites.remove(i);
+        } else {
+            if let Some(preview) = preview {
+                self.store_preview(&saved.preview_hash, preview);
+            }
+            self.favourites.insert(0, saved);
+        }
+        self.save();
+    }
+
+    // moves a gif that was just sent to the front of the recent list
+    pub fn record_sent(&mut self, saved: SavedGif, preview: Option<&[u8]>) {
+        if let Some(preview) = preview {
+            self.store_preview(&saved.preview_hash, preview);
+        }
+        self.recent.retain(|r| !r.same(&saved));
+        self.recent.insert(0, saved);
+        self.recent.truncate(MAX_RECENT);
+        self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn saved(id: &str) -> SavedGif {
+        SavedGif {
+            gif: GifRef {
+                provider: "Tenor".to_string(),
+                id: id.to_string(),
+                url: format!("https://media.tenor.com/{id}.gif"),
+                preview_url: format!("https://media.tenor.com/{id}-tiny.gif"),
+            },
+            shareable: true,
+            preview_hash: format!("hash-{id}"),
+        }
+    }
+
+    #[test]
+    fn lists_survive_a_restart() {
+        let dir = TempDir::new("saved-restart");
+        let mut gifs = SavedGifs::load(dir.path().to_path_buf());
+        gifs.toggle_favourite(saved("a"), Some(b"preview a"));
+        gifs.record_sent(saved("b"), Some(b"preview b"));
+
+        let reloaded = SavedGifs::load(dir.path().to_path_buf());
+        assert_eq!(reloaded.favourites, [saved("a")]);
+        assert_eq!(reloaded.recent, [saved("b")]);
+        assert_eq!(reloaded.preview("hash-a").as_deref(), Some(&b"preview a"[..]));
+    }
+
+    #[test]
+    fn favourites_toggle() {
+        let dir = TempDir::new("saved-toggle");
+        let mut gifs = SavedGifs::load(dir.path().to_path_buf());
+        gifs.toggle_favourite(saved("a"), None);
+        assert!(gifs.is_favourite(&saved("a").gif));
+        gifs.toggle_favourite(saved("a"), None);
+        assert!(!gifs.is_favourite(&saved("a").gif));
+    }
+
+    #[test]
+    fn recent_moves_resent_gifs_to_the_front() {
+        let dir = TempDir::new("saved-recent");
+        let mut gifs = SavedGifs::load(dir.path().to_path_buf());
+        for i in 0..MAX_RECENT + 5 {
+            gifs.record_sent(saved(&i.to_string()), None);
+        }
+        assert_eq!(gifs.recent.len(), MAX_RECENT);
+
+        gifs.record_sent(saved("10"), None);
+        assert_eq!(gifs.recent[0].gif.id, "10");
+        assert_eq!(gifs.recent.iter().filter(|r| r.gif.id == "10").count(), 1);
+    }
+}
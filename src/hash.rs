@@ -0,0 +1,9 @@
+use sha2::{Digest, Sha256};
+
+// hex encoded SHA-256 of some content, used to identify avatars and blobs
+pub fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
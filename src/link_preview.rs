@@ -0,0 +1,500 @@
+// page title / OpenGraph previews for links posted in chat
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    sync::Arc,
+};
+use tokio::sync::mpsc::{Receiver, Sender, channel};
+
+use crate::{
+    attachment::validate,
+    hash::content_hash,
+    message::MessageType,
+    network::{client::NetworkClient, public},
+    ui::texture_cache::TextureCache,
+};
+
+// only the start of a page is searched for metadata
+const MAX_PAGE_BYTES: usize = 512 * 1024;
+const MAX_IMAGE_BYTES: usize = 2 * 1024 * 1024;
+// how much the server keeps of the previews it fetched for clients
+pub const PREVIEW_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+// who fetches previews: nobody, this client, or the server on our behalf
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PreviewMode {
+    Off,
+    #[default]
+    Client,
+    Server,
+}
+
+impl PreviewMode {
+    pub const ALL: [PreviewMode; 3] = [PreviewMode::Off, PreviewMode::Client, PreviewMode::Server];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PreviewMode::Off => "off",
+            PreviewMode::Client => "fetch myself",
+            PreviewMode::Server => "fetch via server",
+        }
+    }
+}
+
+// an empty preview (no title and no image) means the page had nothing to show
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub image: Vec<u8>,
+}
+
+impl LinkPreview {
+    // roughly how much memory the preview takes up
+    fn size(&self) -> usize {
+        let text = [&self.title, &self.description, &self.image_url]
+            .iter()
+            .map(|s| s.as_ref().map_or(0, String::len))
+            .sum::<usize>();
+        self.url.len() + text + self.image.len()
+    }
+}
+
+// previews the server fetched, the oldest are dropped once they take up more
+// than the budget
+pub(crate) struct PreviewCache {
+    previews: HashMap<String, LinkPreview>,
+    // urls, oldest first
+    order: VecDeque<String>,
+    used: usize,
+    budget: usize,
+}
+
+impl Default for PreviewCache {
+    fn default() -> Self {
+        Self::new(PREVIEW_CACHE_BYTES)
+    }
+}
+
+impl PreviewCache {
+    pub fn new(budget: usize) -> Self {
+        Self { previews: HashMap::new(), order: VecDeque::new(), used: 0, budget }
+    }
+
+    pub fn get(&self, url: &str) -> Option<&LinkPreview> {
+        self.previews.get(url)
+    }
+
+    pub fn insert(&mut self, preview: LinkPreview) {
+        if let Some(old) = self.previews.remove(&preview.url) {
+            self.used -= old.size();
+            self.order.retain(|url| *url != preview.url);
+        }
+        self.used += preview.size();
+        self.order.push_back(preview.url.clone());
+        self.previews.insert(preview.url.clone(), preview);
+
+        while self.used > self.budget
+            && let Some(oldest) = self.order.pop_front()
+        {
+            if let Some(old) = self.previews.remove(&oldest) {
+                self.used -= old.size();
+            }
+        }
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// attribute pairs of a single tag, like `<meta property="og:title" content="x">`
+fn tag_attributes(tag: &str) -> HashMap<String, String> {
+    let mut attrs = HashMap::new();
+    let mut rest = tag;
+
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq]
+            .rsplit(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let value_start = rest[eq + 1..].trim_start();
+        let (value, remaining) = match value_start.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                let body = &value_start[1..];
+                let end = body.find(q).unwrap_or(body.len());
+                (&body[..end], &body[(end + 1).min(body.len())..])
+            }
+            _ => {
+                let end = value_start
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(value_start.len());
+                (&value_start[..end], &value_start[end..])
+            }
+        };
+        attrs.insert(name, decode_entities(value));
+        rest = remaining;
+    }
+    attrs
+}
+
+// pulls the title, description and image out of a page
+pub fn parse_html(page_url: &reqwest::Url, html: &str) -> LinkPreview {
+    // lowercasing ascii keeps byte offsets, so indices can be used on the original
+    let lower = html.to_ascii_lowercase();
+    let mut meta = HashMap::<String, String>::new();
+
+    for (start, _) in lower.match_indices("<meta") {
+        let end = lower[start..].find('>').map_or(lower.len(), |e| start + e);
+        let attrs = tag_attributes(&html[start + 5..end]);
+        let key = attrs.get("property").or_else(|| attrs.get("name"));
+        if let (Some(key), Some(content)) = (key, attrs.get("content")) {
+            meta.entry(key.to_ascii_lowercase()).or_insert_with(|| content.clone());
+        }
+    }
+
+    let title_tag = lower.find("<title").and_then(|start| {
+        let open = start + lower[start..].find('>')? + 1;
+        let close = open + lower[open..].find("</title")?;
+        Some(decode_entities(&html[open..close]))
+    });
+
+    let non_empty = |s: Option<String>| s.filter(|s| !s.is_empty());
+    LinkPreview {
+        url: page_url.to_string(),
+        title: non_empty(meta.get("og:title").cloned().or(title_tag)),
+        description: non_empty(
+            meta.get("og:description")
+                .or_else(|| meta.get("description"))
+                .cloned(),
+        ),
+        // relative image paths are resolved against the page
+        image_url: meta
+            .get("og:image")
+            .and_then(|img| page_url.join(img).ok())
+            .map(|u| u.to_string()),
+        image: Vec::new(),
+    }
+}
+
+// reads at most `limit` bytes of a response body
+pub(crate) async fn read_limited(mut response: reqwest::Response, limit: usize) -> Result<Vec<u8>, reqwest::Error> {
+    let mut body = Vec::<u8>::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= limit {
+            body.truncate(limit);
+            break;
+        }
+    }
+    Ok(body)
+}
+
+// fetches the preview of a link a peer posted. pass a public::client so
+// names that resolve to our own network are refused as well
+pub async fn fetch(client: &reqwest::Client, url: &str) -> Result<LinkPreview, Box<dyn Error + Send + Sync>> {
+    let page_url = reqwest::Url::parse(url)?;
+    public::check_url(&page_url)?;
+
+    let response = client.get(page_url.clone()).send().await?.error_for_status()?;
+    let is_html = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"));
+    if !is_html {
+        return Ok(LinkPreview { url: url.to_string(), ..Default::default() });
+    }
+
+    let body = read_limited(response, MAX_PAGE_BYTES).await?;
+    let mut preview = parse_html(&page_url, &String::from_utf8_lossy(&body));
+    preview.url = url.to_string();
+
+    // a missing or broken image still leaves a useful text preview
+    if let Some(image_url) = &preview.image_url {
+        match fetch_image(client, image_url).await {
+            Ok(bytes) => preview.image = bytes,
+            Err(e) => eprintln!("no preview image from {image_url}: {e}"),
+        }
+    }
+
+    Ok(preview)
+}
+
+// the page's image, only kept if it's one every client can decode safely
+async fn fetch_image(client: &reqwest::Client, url: &str) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    public::check_url(&reqwest::Url::parse(url)?)?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    let bytes = read_limited(response, MAX_IMAGE_BYTES + 1).await?;
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err("the image is too large".into());
+    }
+    validate::check_image(&bytes)?;
+    Ok(bytes)
+}
+
+enum PreviewState {
+    Loading,
+    // the image by content hash, so it's drawn from the texture cache
+    Ready(LinkPreview, Option<(String, Arc<[u8]>)>),
+}
+
+// client side cache of previews, filled by our own fetches or by the server
+pub struct LinkPreviews {
+    client: reqwest::Client,
+    cache: HashMap<String, PreviewState>,
+    tx: Sender<LinkPreview>,
+    rx: Receiver<LinkPreview>,
+}
+
+impl Default for LinkPreviews {
+    fn default() -> Self {
+        let (tx, rx) = channel::<LinkPreview>(32);
+        Self {
+            client: public::client(),
+            cache: HashMap::new(),
+            tx,
+            rx,
+        }
+    }
+}
+
+impl LinkPreviews {
+    // previews from the server are checked again, it passes on what it got
+    pub fn insert(&mut self, mut preview: LinkPreview) {
+        let image = std::mem::take(&mut preview.image);
+        let image = match validate::check_image(&image) {
+            Ok(_) => Some((content_hash(&image), image.into())),
+            Err(e) if !image.is_empty() => {
+                eprintln!("dropped the preview image of {}: {e}", preview.url);
+                None
+            },
+            Err(_) => None,
+        };
+        self.cache.insert(preview.url.clone(), PreviewState::Ready(preview, image));
+    }
+
+    // collects previews fetched by this client
+    pub fn poll(&mut self) {
+        while let Ok(preview) = self.rx.try_recv() {
+            self.insert(preview);
+        }
+    }
+
+    fn request(
+        &mut self,
+        url: &str,
+        mode: PreviewMode,
+        net: Option<&NetworkClient>,
+        rt: &tokio::runtime::Handle,
+    ) {
+        match mode {
+            PreviewMode::Off => return,
+            PreviewMode::Client => {
+                let client = self.client.clone();
+                let tx = self.tx.clone();
+                let url = url.to_string();
+                rt.spawn(async move {
+                    let preview = match fetch(&client, &url).await {
+                        Ok(preview) => preview,
+                        Err(e) => {
+                            eprintln!("failed to fetch link preview for {url}: {e}");
+                            LinkPreview { url, ..Default::default() }
+                        }
+                    };
+                    let _ = tx.send(preview).await;
+                });
+            }
+            PreviewMode::Server => match net {
+                Some(net) => net.send(MessageType::LinkPreviewRequest(url.to_string())),
+                None => return,
+            },
+        }
+        self.cache.insert(url.to_string(), PreviewState::Loading);
+    }
+
+    // shows a card for each link, requesting previews that are not cached yet
+    pub fn show_cards(
+        &mut self,
+        ui: &mut egui::Ui,
+        links: &[String],
+        textures: &mut TextureCache,
+        mode: PreviewMode,
+        net: Option<&NetworkClient>,
+        rt: &tokio::runtime::Handle,
+    ) {
+        if mode == PreviewMode::Off {
+            return;
+        }
+
+        for url in links {
+            match self.cache.get(url) {
+                None => self.request(url, mode, net, rt),
+                Some(PreviewState::Loading) => {}
+                Some(PreviewState::Ready(preview, None)) if preview.title.is_none() => {} // nothing to show
+                Some(PreviewState::Ready(preview, image)) => preview_card(ui, preview, image.as_ref(), textures),
+            }
+        }
+    }
+}
+
+fn preview_card(
+    ui: &mut egui::Ui,
+    preview: &LinkPreview,
+    image: Option<&(String, Arc<[u8]>)>,
+    textures: &mut TextureCache,
+) {
+    egui::Frame::group(ui.style())
+        .corner_radius(4)
+        .show(ui, |ui| {
+            ui.set_max_width(350.0);
+            ui.horizontal(|ui| {
+                if let Some((hash, bytes)) = image
+                    && let Some(texture) = textures.get(ui.ctx(), hash, || Some(Arc::clone(bytes)))
+                {
+                    ui.add(
+                        egui::Image::from_texture(texture)
+                            .max_size(egui::vec2(80.0, 80.0))
+                            .corner_radius(4),
+                    );
+                }
+                ui.vertical(|ui| {
+                    let title = preview.title.as_deref().unwrap_or(&preview.url);
+                    ui.hyperlink_to(egui::RichText::new(title).strong(), &preview.url);
+                    if let Some(description) = &preview.description {
+                        ui.add(
+                            egui::Label::new(egui::RichText::new(description).weak().small())
+                                .truncate(),
+                        );
+                    }
+                    if let Ok(url) = reqwest::Url::parse(&preview.url)
+                        && let Some(host) = url.host_str()
+                    {
+                        ui.label(egui::RichText::new(host).weak().small());
+                    }
+                });
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{StubResponse, encode_image, serve};
+
+    // the stub listens on loopback, which the checks only let through by name
+    fn stub_url(addr: std::net::SocketAddr, path: &str) -> String {
+        format!("http://localhost:{}{path}", addr.port())
+    }
+
+    #[test]
+    fn parses_open_graph_tags() {
+        let url = reqwest::Url::parse("http://example.com/posts/1").unwrap();
+        let html = r#"<html><head>
+            <title>Fallback</title>
+            <META property="og:title" content="Tom &amp; Jerry">
+            <meta name='description' content='cat and mouse'>
+            <meta content="/img/cover.png" property="og:image" />
+            </head></html>"#;
+
+        let preview = parse_html(&url, html);
+        assert_eq!(preview.title.as_deref(), Some("Tom & Jerry"));
+        assert_eq!(preview.description.as_deref(), Some("cat and mouse"));
+        assert_eq!(preview.image_url.as_deref(), Some("http://example.com/img/cover.png"));
+    }
+
+    #[test]
+    fn falls_back_to_title_tag() {
+        let url = reqwest::Url::parse("http://example.com/").unwrap();
+        let preview = parse_html(&url, "<title>\n  Plain   page\n</title>");
+        assert_eq!(preview.title.as_deref(), Some("Plain page"));
+        assert_eq!(preview.image_url, None);
+    }
+
+    #[tokio::test]
+    async fn fetches_page_and_image_from_stub() {
+        let page = r#"<head><meta property="og:title" content="Stub"><meta property="og:image" content="/cover.png"></head>"#;
+        let cover = encode_image(4, 4, image::ImageFormat::Png);
+        let (addr, log) = serve(vec![
+            ("/page", StubResponse::ok("text/html; charset=utf-8", page)),
+            ("/cover.png", StubResponse::ok("image/png", cover.clone())),
+        ])
+        .await;
+
+        let url = stub_url(addr, "/page");
+        let preview = fetch(&reqwest::Client::new(), &url).await.unwrap();
+        assert_eq!(preview.url, url);
+        assert_eq!(preview.title.as_deref(), Some("Stub"));
+        assert_eq!(preview.image, cover);
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn images_that_fail_the_checks_are_dropped() {
+        let page = r#"<title>Stub</title><meta property="og:image" content="/cover.png">"#;
+        let (addr, _) = serve(vec![
+            ("/page", StubResponse::ok("text/html", page)),
+            ("/cover.png", StubResponse::ok("image/png", "not really a png")),
+        ])
+        .await;
+
+        let preview = fetch(&reqwest::Client::new(), &stub_url(addr, "/page")).await.unwrap();
+        assert_eq!(preview.title.as_deref(), Some("Stub"));
+        assert!(preview.image.is_empty());
+    }
+
+    #[tokio::test]
+    async fn links_to_private_addresses_are_not_fetched() {
+        let (addr, log) = serve(vec![("/page", StubResponse::ok("text/html", "<title>x</title>"))]).await;
+        assert!(fetch(&reqwest::Client::new(), &format!("http://{addr}/page")).await.is_err());
+        // by name too, once the client resolves it
+        assert!(fetch(&public::client(), &stub_url(addr, "/page")).await.is_err());
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn non_html_links_have_empty_previews() {
+        let (addr, _) = serve(vec![("/file.zip", StubResponse::ok("application/zip", "PK"))]).await;
+        let url = stub_url(addr, "/file.zip");
+        let preview = fetch(&reqwest::Client::new(), &url).await.unwrap();
+        assert_eq!(preview, LinkPreview { url, ..Default::default() });
+    }
+
+    #[tokio::test]
+    async fn http_errors_are_reported() {
+        let (addr, _) = serve(vec![]).await;
+        assert!(fetch(&reqwest::Client::new(), &stub_url(addr, "/missing")).await.is_err());
+        assert!(fetch(&reqwest::Client::new(), "ftp://example.com/").await.is_err());
+    }
+
+    #[test]
+    fn server_cache_drops_the_oldest_previews() {
+        let preview = |url: &str| LinkPreview { url: url.to_string(), image: vec![0; 100], ..Default::default() };
+        let mut cache = PreviewCache::new(250);
+        cache.insert(preview("a"));
+        cache.insert(preview("b"));
+        cache.insert(preview("c"));
+
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+        assert!(cache.get("c").is_some());
+        assert_eq!(cache.used, 202);
+
+        // fetching a url again replaces its entry
+        cache.insert(preview("b"));
+        assert_eq!(cache.used, 202);
+    }
+}
@@ -7,11 +7,22 @@ mod gif;
 mod views;
 mod app;
 mod room;
+mod mention;
+mod emoji;
+mod sticker;
+mod markup;
+mod link_preview;
+mod hash;
+mod attachment;
+#[cfg(test)]
+mod test_support;
 
-use tokio;
 use crate::app::app::App;
 
 fn main() -> eframe::Result<()> {
+    // settings like TENOR_API_KEY and STICKER_DIR can come from a .env file
+    dotenvy::dotenv().ok();
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_inner_size([400.0, 400.0]),
         ..Default::default()
@@ -0,0 +1,416 @@
+// lightweight markup used in chat messages
+//
+// inline: **bold**, *italics* or _italics_, ~~strike~~, `code`, ||spoiler||
+// links: bare http:// and https:// urls
+// blocks: ```lang fenced code```, > block quotes
+// a backslash before any markup character makes it literal
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpanStyle {
+    pub bold: bool,
+    pub italics: bool,
+    pub strike: bool,
+    pub code: bool,
+    pub spoiler: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: SpanStyle,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Span>),
+    Quote(Vec<Span>),
+    Code { lang: Option<String>, code: String },
+}
+
+const ESCAPABLE: &[char] = &['\\', '*', '_', '~', '`', '|', '>'];
+
+#[derive(Clone, Copy)]
+enum Marker {
+    Bold,
+    Strike,
+    Spoiler,
+    Italics(char),
+    Code,
+}
+
+impl Marker {
+    fn token(&self) -> &'static str {
+        match self {
+            Marker::Bold => "**",
+            Marker::Strike => "~~",
+            Marker::Spoiler => "||",
+            Marker::Italics('_') => "_",
+            Marker::Italics(_) => "*",
+            Marker::Code => "`",
+        }
+    }
+
+    fn apply(&self, mut style: SpanStyle) -> SpanStyle {
+        match self {
+            Marker::Bold => style.bold = true,
+            Marker::Strike => style.strike = true,
+            Marker::Spoiler => style.spoiler = true,
+            Marker::Italics(_) => style.italics = true,
+            Marker::Code => style.code = true,
+        }
+        style
+    }
+}
+
+// splits message text into blocks, then parses the inline markup of each
+pub fn parse(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::<Block>::new();
+    let mut paragraph = Vec::<&str>::new();
+    let mut quote = Vec::<&str>::new();
+    let mut lines = text.lines();
+
+    fn flush(blocks: &mut Vec<Block>, lines: &mut Vec<&str>, quote: bool) {
+        if lines.is_empty() {
+            return;
+        }
+        let spans = parse_inline(&lines.join("\n"));
+        lines.clear();
+        blocks.push(if quote { Block::Quote(spans) } else { Block::Paragraph(spans) });
+    }
+
+    while let Some(line) = lines.next() {
+        if let Some(lang) = line.trim_start().strip_prefix("```") {
+            flush(&mut blocks, &mut paragraph, false);
+            flush(&mut blocks, &mut quote, true);
+
+            // an unterminated fence runs to the end of the message
+            let mut code = Vec::<&str>::new();
+            for line in lines.by_ref() {
+                if line.trim_start().starts_with("```") {
+                    break;
+                }
+                code.push(line);
+            }
+
+            let lang = lang.trim();
+            blocks.push(Block::Code {
+                lang: (!lang.is_empty()).then(|| lang.to_string()),
+                code: code.join("\n"),
+            });
+        } else if let Some(rest) = line.strip_prefix('>') {
+            flush(&mut blocks, &mut paragraph, false);
+            quote.push(rest.strip_prefix(' ').unwrap_or(rest));
+        } else {
+            flush(&mut blocks, &mut quote, true);
+            paragraph.push(line);
+        }
+    }
+
+    flush(&mut blocks, &mut paragraph, false);
+    flush(&mut blocks, &mut quote, true);
+    blocks
+}
+
+// parses inline markup into styled spans
+pub fn parse_inline(text: &str) -> Vec<Span> {
+    let mut spans = Vec::<Span>::new();
+    parse_into(text, SpanStyle::default(), &mut spans);
+    spans
+}
+
+// every link in the parsed blocks, in order and without duplicates. links
+// inside spoilers are left out so their previews don't give them away
+pub fn links(blocks: &[Block]) -> Vec<String> {
+    let mut links = Vec::<String>::new();
+    for block in blocks {
+        if let Block::Paragraph(spans) | Block::Quote(spans) = block {
+            for link in spans.iter().filter(|s| !s.style.spoiler).filter_map(|s| s.link.as_ref()) {
+                if !links.contains(link) {
+                    links.push(link.clone());
+                }
+            }
+        }
+    }
+    links
+}
+
+fn push_text(spans: &mut Vec<Span>, text: &str, style: SpanStyle) {
+    if text.is_empty() {
+        return;
+    }
+    match spans.last_mut() {
+        Some(last) if last.style == style && last.link.is_none() => last.text.push_str(text),
+        _ => spans.push(Span { text: text.to_string(), style, link: None }),
+    }
+}
+
+// length of the url starting at `rest`, trailing punctuation is left out
+fn url_len(rest: &str) -> Option<usize> {
+    let scheme = ["https://", "http://"].into_iter().find(|s| rest.starts_with(s))?;
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"')
+        .unwrap_or(rest.len());
+    let url = rest[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '*', '_', '~', '|', '`']);
+    (url.len() > scheme.len()).then_some(url.len())
+}
+
+fn is_word_char(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric())
+}
+
+// the marker opening at the start of `rest`, if any
+fn marker_at(rest: &str, prev: Option<char>) -> Option<Marker> {
+    if rest.starts_with("**") {
+        Some(Marker::Bold)
+    } else if rest.starts_with("~~") {
+        Some(Marker::Strike)
+    } else if rest.starts_with("||") {
+        Some(Marker::Spoiler)
+    } else if rest.starts_with('`') {
+        Some(Marker::Code)
+    } else if rest.starts_with('*') {
+        Some(Marker::Italics('*'))
+    } else if rest.starts_with('_') && !is_word_char(prev) {
+        // underscores inside words (snake_case) are not markup
+        Some(Marker::Italics('_'))
+    } else {
+        None
+    }
+}
+
+// byte offset of the closing marker in `rest`, skipping escaped characters
+fn find_close(rest: &str, marker: Marker) -> Option<usize> {
+    let token = marker.token();
+    let mut chars = rest.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' && !matches!(marker, Marker::Code) {
+            chars.next();
+            continue;
+        }
+        if !rest[i..].starts_with(token) || i == 0 {
+            continue;
+        }
+        // a single * must not be the start of a ** pair
+        if matches!(marker, Marker::Italics('*')) && rest[i..].starts_with("**") {
+            chars.next();
+            continue;
+        }
+        if matches!(marker, Marker::Italics('_')) && is_word_char(rest[i + 1..].chars().next()) {
+            continue;
+        }
+        // in "***" the single * closes first, so bold closes on the last two
+        if matches!(marker, Marker::Bold) && rest[i..].starts_with("***") {
+            return Some(i + 1);
+        }
+        return Some(i);
+    }
+    None
+}
+
+fn parse_into(text: &str, style: SpanStyle, spans: &mut Vec<Span>) {
+    let mut plain_start = 0;
+    let mut i = 0;
+    let mut prev: Option<char> = None;
+
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap_or_default();
+
+        if c == '\\' {
+            let next = rest[1..].chars().next();
+            if let Some(next) = next.filter(|n| ESCAPABLE.contains(n)) {
+                push_text(spans, &text[plain_start..i], style);
+                push_text(spans, &next.to_string(), style);
+                i += 1 + next.len_utf8();
+                plain_start = i;
+                prev = Some(next);
+                continue;
+            }
+        }
+
+        if !style.code
+            && !is_word_char(prev)
+            && let Some(len) = url_len(rest)
+        {
+            push_text(spans, &text[plain_start..i], style);
+            let url = &rest[..len];
+            spans.push(Span { text: url.to_string(), style, link: Some(url.to_string()) });
+            i += len;
+            plain_start = i;
+            prev = url.chars().last();
+            continue;
+        }
+
+        if let Some(marker) = marker_at(rest, prev) {
+            let open = marker.token().len();
+            if let Some(close) = find_close(&rest[open..], marker) {
+                push_text(spans, &text[plain_start..i], style);
+                let inner = &rest[open..open + close];
+                let inner_style = marker.apply(style);
+
+                // code spans are taken literally
+                if matches!(marker, Marker::Code) {
+                    push_text(spans, inner, inner_style);
+                } else {
+                    parse_into(inner, inner_style, spans);
+                }
+
+                i += open + close + open;
+                plain_start = i;
+                prev = marker.token().chars().last();
+                continue;
+            }
+        }
+
+        i += c.len_utf8();
+        prev = Some(c);
+    }
+
+    push_text(spans, &text[plain_start..], style);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Span {
+        Span { text: text.to_string(), style: SpanStyle::default(), link: None }
+    }
+
+    fn styled(text: &str, f: impl FnOnce(&mut SpanStyle)) -> Span {
+        let mut style = SpanStyle::default();
+        f(&mut style);
+        Span { text: text.to_string(), style, link: None }
+    }
+
+    #[test]
+    fn plain_text_is_one_span() {
+        assert_eq!(parse_inline("hello world"), vec![plain("hello world")]);
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(
+            parse_inline("a **b** *c* _d_ ~~e~~ `f` ||g||"),
+            vec![
+                plain("a "),
+                styled("b", |s| s.bold = true),
+                plain(" "),
+                styled("c", |s| s.italics = true),
+                plain(" "),
+                styled("d", |s| s.italics = true),
+                plain(" "),
+                styled("e", |s| s.strike = true),
+                plain(" "),
+                styled("f", |s| s.code = true),
+                plain(" "),
+                styled("g", |s| s.spoiler = true),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_styles() {
+        assert_eq!(
+            parse_inline("**bold *both***"),
+            vec![
+                styled("bold ", |s| s.bold = true),
+                styled("both", |s| {
+                    s.bold = true;
+                    s.italics = true;
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn unclosed_markers_are_literal() {
+        assert_eq!(parse_inline("2 * 3 and **oops"), vec![plain("2 * 3 and **oops")]);
+    }
+
+    #[test]
+    fn escapes_make_markers_literal() {
+        assert_eq!(parse_inline(r"\*not italics\* \\"), vec![plain(r"*not italics* \")]);
+        assert_eq!(parse_inline(r"a\b"), vec![plain(r"a\b")]);
+    }
+
+    #[test]
+    fn code_spans_are_literal() {
+        assert_eq!(
+            parse_inline("`**x** \\`"),
+            vec![styled("**x** \\", |s| s.code = true)]
+        );
+    }
+
+    #[test]
+    fn underscores_inside_words_are_not_markup() {
+        assert_eq!(parse_inline("snake_case_name"), vec![plain("snake_case_name")]);
+    }
+
+    #[test]
+    fn bare_urls_become_links() {
+        let link = |url: &str| Span {
+            text: url.to_string(),
+            style: SpanStyle::default(),
+            link: Some(url.to_string()),
+        };
+        assert_eq!(
+            parse_inline("see https://example.com/a_b_c, or (http://x.org)."),
+            vec![
+                plain("see "),
+                link("https://example.com/a_b_c"),
+                plain(", or ("),
+                link("http://x.org"),
+                plain(")."),
+            ]
+        );
+        assert_eq!(parse_inline("`https://example.com`"), vec![styled("https://example.com", |s| s.code = true)]);
+        assert_eq!(parse_inline("https://"), vec![plain("https://")]);
+    }
+
+    #[test]
+    fn links_are_collected_once() {
+        let blocks = parse("https://a.com\n> https://b.com https://a.com\n```\nhttps://c.com\n```");
+        assert_eq!(links(&blocks), vec!["https://a.com".to_string(), "https://b.com".to_string()]);
+    }
+
+    #[test]
+    fn links_in_spoilers_are_not_collected() {
+        let blocks = parse("||https://secret.com|| https://open.com");
+        assert_eq!(links(&blocks), vec!["https://open.com".to_string()]);
+    }
+
+    #[test]
+    fn fenced_code_block() {
+        assert_eq!(
+            parse("before\n```rust\nfn main() {}\n```\nafter"),
+            vec![
+                Block::Paragraph(vec![plain("before")]),
+                Block::Code { lang: Some("rust".into()), code: "fn main() {}".into() },
+                Block::Paragraph(vec![plain("after")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_fence_runs_to_end() {
+        assert_eq!(
+            parse("```\n**x**"),
+            vec![Block::Code { lang: None, code: "**x**".into() }]
+        );
+    }
+
+    #[test]
+    fn block_quotes_group_lines() {
+        assert_eq!(
+            parse("> one\n> **two**\nthree"),
+            vec![
+                Block::Quote(vec![plain("one\n"), styled("two", |s| s.bold = true)]),
+                Block::Paragraph(vec![plain("three")]),
+            ]
+        );
+        assert_eq!(parse(r"\> not a quote"), vec![Block::Paragraph(vec![plain("> not a quote")])]);
+    }
+}
@@ -0,0 +1,172 @@
+// @mention parsing and the inbox of messages that mentioned the local user
+
+// characters that may not directly follow a mentioned name
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
+}
+
+// returns the names from the roster that are mentioned in the text, names are
+// matched case-insensitively and the longest matching name wins
+pub fn resolve<'a>(text: &str, roster: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let mut roster: Vec<&String> = roster.into_iter().filter(|n| !n.is_empty()).collect();
+    roster.sort_by_key(|n| std::cmp::Reverse(n.len()));
+
+    let mut mentions = Vec::<String>::new();
+    for (at, _) in text.match_indices('@') {
+        // an @ inside a word (like an email address) is not a mention
+        if text[..at].chars().next_back().is_some_and(is_name_char) {
+            continue;
+        }
+
+        let rest = &text[at + 1..];
+        let found = roster.iter().find(|name| {
+            rest.get(..name.len())
+                .is_some_and(|candidate| candidate.eq_ignore_ascii_case(name))
+                && !rest[name.len()..].chars().next().is_some_and(is_name_char)
+        });
+
+        if let Some(name) = found
+            && !mentions.contains(name)
+        {
+            mentions.push((*name).clone());
+        }
+    }
+    mentions
+}
+
+// the partially typed mention at the end of the composer text, as the byte
+// index of the @ and the text typed after it
+pub fn partial_at_end(text: &str) -> Option<(usize, &str)> {
+    let at = text.rfind('@')?;
+    if text[..at].chars().next_back().is_some_and(is_name_char) {
+        return None;
+    }
+    let prefix = &text[at + 1..];
+    if prefix.chars().all(|c| !c.is_whitespace()) {
+        Some((at, prefix))
+    } else {
+        None
+    }
+}
+
+// roster names that complete the given prefix
+pub fn suggestions<'a>(prefix: &str, roster: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let mut names: Vec<String> = roster
+        .into_iter()
+        .filter(|name| name.to_lowercase().starts_with(&prefix))
+        .cloned()
+        .collect();
+    names.sort();
+    names
+}
+
+pub struct MentionEntry {
+    // the room the mention was made in
+    pub room: String,
+    pub user_name: String,
+    pub message: String,
+    pub timestamp: String,
+}
+
+// every message that mentioned the local user, newest last
+#[derive(Default)]
+pub struct MentionInbox {
+    pub entries: Vec<MentionEntry>,
+    // number of entries that had been seen when the inbox was last opened
+    pub seen: usize,
+    // entries from this index on are shown as new while the inbox is open
+    pub new_from: usize,
+}
+
+impl MentionInbox {
+    pub fn push(&mut self, entry: MentionEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn unread(&self) -> usize {
+        self.entries.len() - self.seen
+    }
+
+    pub fn mark_seen(&mut self) {
+        self.new_from = self.seen;
+        self.seen = self.entries.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roster(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn entry(message: &str) -> MentionEntry {
+        MentionEntry {
+            room: "main".to_string(),
+            user_name: "ann".to_string(),
+            message: message.to_string(),
+            timestamp: String::new(),
+        }
+    }
+
+    #[test]
+    fn resolves_roster_names() {
+        let names = roster(&["ann", "bob"]);
+        assert_eq!(resolve("hi @ann and @BOB", &names), ["ann", "bob"]);
+        assert_eq!(resolve("@ann @ann", &names), ["ann"]);
+        assert!(resolve("@carl", &names).is_empty());
+    }
+
+    #[test]
+    fn longest_name_wins() {
+        let names = roster(&["ann", "ann.b"]);
+        assert_eq!(resolve("@ann.b", &names), ["ann.b"]);
+        assert_eq!(resolve("@ann, hi", &names), ["ann"]);
+    }
+
+    #[test]
+    fn at_inside_a_word_is_not_a_mention() {
+        let names = roster(&["ann", "example"]);
+        assert!(resolve("mail me@example please", &names).is_empty());
+        // the name has to end where the mention does
+        assert!(resolve("@annie", &names).is_empty());
+    }
+
+    #[test]
+    fn partial_mention_at_end() {
+        assert_eq!(partial_at_end("hi @an"), Some((3, "an")));
+        assert_eq!(partial_at_end("@"), Some((0, "")));
+        assert_eq!(partial_at_end("hi @ann "), None);
+        assert_eq!(partial_at_end("me@exam"), None);
+        assert_eq!(partial_at_end("no mention"), None);
+    }
+
+    #[test]
+    fn suggestions_complete_the_prefix() {
+        let names = roster(&["bob", "Anna", "ann", "carl"]);
+        assert_eq!(suggestions("an", &names), ["Anna", "ann"]);
+        assert_eq!(suggestions("", &names).len(), 4);
+        assert!(suggestions("z", &names).is_empty());
+    }
+
+    #[test]
+    fn inbox_counts_unread() {
+        let mut inbox = MentionInbox::default();
+        inbox.push(entry("one"));
+        inbox.push(entry("two"));
+        assert_eq!(inbox.unread(), 2);
+
+        inbox.mark_seen();
+        assert_eq!(inbox.unread(), 0);
+        assert_eq!(inbox.new_from, 0);
+
+        inbox.push(entry("three"));
+        assert_eq!(inbox.unread(), 1);
+        inbox.mark_seen();
+        // only the entry that arrived since the last look is new
+        assert_eq!(inbox.new_from, 2);
+        assert_eq!(inbox.entries[2].room, "main");
+    }
+}
@@ -1,76 +1,165 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, fmt::Debug, sync::Arc
+    collections::HashMap, fmt::Debug
 };
+use egui::load::SizedTexture;
 use uuid::Uuid;
 
+use crate::{
+    attachment::{AttachmentDescriptor, BlobChunk, FetchBlob, UploadAck, UploadRejected},
+    gif::GifRef,
+    link_preview::LinkPreview,
+    markup,
+    sticker::{Sticker, StickerPack},
+    ui::rich_text,
+    user::presence::{Presence, UserStatus},
+};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MessageType {
     Message(Message),
     Notification(Notification),
     Connect(Connect),
-    UserList(HashMap<String, String>),
+    Avatar(Avatar),
+    // the server's custom emoji, sent to a client when it joins
+    CustomEmojiList(CustomEmojiList),
+    // an emoji an admin added, sent to the server and then to everyone
+    CustomEmoji(CustomEmoji),
+    // the server's sticker packs, sent to a client when it joins
+    StickerPacks(Vec<StickerPack>),
+    UserList(HashMap<String, UserStatus>),
     Disconnect(Disconnect),
+    Status(StatusUpdate),
+    // asks the server to fetch a preview, answered with LinkPreview
+    LinkPreviewRequest(String),
+    LinkPreview(LinkPreview),
+    // attachment transfers, see the attachment module
+    UploadChunk(BlobChunk),
+    UploadAck(UploadAck),
+    UploadRejected(UploadRejected),
+    // asks how much of an upload the server has, answered with UploadAck
+    UploadResume(String),
+    UploadCancel(String),
+    FetchBlob(FetchBlob),
+    FetchCancel(String),
+    BlobChunk(BlobChunk),
+    BlobMissing(String),
 }
 
+// space kept for an avatar that hasn't arrived yet
+const AVATAR_SIZE: f32 = 32.0;
+
 // MESSAGE
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Message {
     pub user_name: String,
-    pub profile_picture: Vec<u8>,
+    // content hash of the sender's avatar, registered once with MessageType::Avatar
+    pub avatar: String,
     pub message: String,
-    pub image: Arc<Vec<u8>>,
+    // uploaded separately, recipients fetch the bytes when they need them
+    #[serde(default)]
+    pub attachments: Vec<AttachmentDescriptor>,
+    // a gif sent by reference, recipients download it from the provider
+    #[serde(default)]
+    pub gif: Option<Box<GifRef>>,
+    // a sticker by id, its bytes are a blob on the server
+    #[serde(default)]
+    pub sticker: Option<Box<Sticker>>,
     pub timestamp: String,
     pub uuid: String,
-    pub uuid_profile_picture: String,
+    // the room the message was sent in, filled in by the server
+    #[serde(default)]
+    pub room: String,
+    // roster names mentioned in the message text
+    #[serde(default)]
+    pub mentions: Vec<String>,
+    // parsed markup, filled in the first time the message is shown
+    #[serde(skip)]
+    pub blocks: Option<Vec<markup::Block>>,
+    #[serde(skip)]
+    pub spoilers_revealed: bool,
 }
 
 impl Default for Message {
     fn default() -> Self {
         Self {
             user_name: "default".to_owned(),
-            profile_picture: Vec::<u8>::new(),
+            avatar: String::new(),
             message: "default message".to_owned(),
-            image: Arc::new(Vec::<u8>::new()),
+            attachments: Vec::new(),
+            gif: None,
+            sticker: None,
             timestamp: chrono::Local::now().to_string(),
             uuid: Uuid::new_v4().to_string(),
-            uuid_profile_picture: Uuid::new_v4().to_string(),
+            room: String::new(),
+            mentions: Vec::new(),
+            blocks: None,
+            spoilers_revealed: false,
         }
     }
 }
 
-impl egui::Widget for &mut Message {
-    fn ui(self, ui: &mut egui::Ui) -> egui::Response {
-        let profile_pic = egui::Image::from_bytes(
-            format!("bytes://{}", self.uuid_profile_picture),
-            self.profile_picture.clone(),
-        )
-        .fit_to_original_size(0.5);
-
-        let bytes = Arc::new(&self.image).to_vec();
-        let attachment =
-            egui::Image::from_bytes(format!("bytes://{}", self.uuid), bytes)
-                .max_size(egui::vec2(250.0, 250.0))
-                .fit_to_exact_size(egui::vec2(250.0, 250.0));
-
-        let response = ui
-            .horizontal(|ui| {
-                ui.add(profile_pic);
-                ui.vertical(|ui| {
-                    ui.horizontal(|ui| {
-                        ui.label(egui::RichText::new(&self.user_name).strong().italics());
-                        ui.label(egui::RichText::new(&self.timestamp).weak().italics());
-                    });
-                    ui.label(egui::RichText::new(&self.message));
-                });
-            })
-            .response;
-        if !self.image.is_empty() {
-            ui.add(attachment);
-        }
+impl Message {
+    // links in the message text, empty until the message has been shown
+    pub fn links(&self) -> Vec<String> {
+        self.blocks.as_deref().map(markup::links).unwrap_or_default()
+    }
 
-        response
+    pub fn mentions_user(&self, user_name: &str) -> bool {
+        self.mentions.iter().any(|m| m.eq_ignore_ascii_case(user_name))
     }
+
+    // shows the message with the sender's avatar, if it has been loaded
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        avatar: Option<SizedTexture>,
+        custom_emoji: &mut rich_text::CustomEmojiLookup,
+    ) -> egui::Response {
+        ui.horizontal(|ui| {
+            match avatar {
+                Some(avatar) => {
+                    ui.add(egui::Image::from_texture(avatar).fit_to_original_size(0.5));
+                },
+                None => {
+                    ui.allocate_exact_size(egui::vec2(AVATAR_SIZE, AVATAR_SIZE), egui::Sense::hover());
+                },
+            }
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new(&self.user_name).strong().italics());
+                    ui.label(egui::RichText::new(&self.timestamp).weak().italics());
+                });
+                let blocks = self.blocks.get_or_insert_with(|| markup::parse(&self.message));
+                if rich_text::show_blocks(ui, &self.uuid, blocks, self.spoilers_revealed, custom_emoji) {
+                    self.spoilers_revealed = !self.spoilers_revealed;
+                }
+            });
+        })
+        .response
+    }
+}
+
+// a user's profile picture, sent once at join and cached by every client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Avatar {
+    pub hash: String,
+    pub bytes: Vec<u8>,
+}
+
+// an image used as :name: in messages, added to the server by an admin
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomEmoji {
+    pub name: String,
+    pub hash: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomEmojiList {
+    pub emoji: Vec<CustomEmoji>,
+    // whether the receiving user is an admin allowed to add emoji
+    pub can_add: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -80,18 +169,36 @@ pub struct Notification {
 
 impl egui::Widget for &mut Notification {
     fn ui(self, ui: &mut egui::Ui) -> egui::Response {
-        let response = ui
-            .vertical(|ui| {
-                ui.label(egui::RichText::new(&self.message).weak());
-            })
-            .response;
-        response
+        ui.vertical(|ui| {
+            ui.label(egui::RichText::new(&self.message).weak());
+        })
+        .response
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Connect {
     pub user_name: String,
+    // only sent to the server, which puts it in the user list and relays
+    // the join without it
+    #[serde(default)]
+    pub status: Option<UserStatus>,
+    // proves the user is an admin, only sent to the server and never relayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+}
+
+impl Connect {
+    // the join as the rest of the room sees it, invisible users don't join
+    // as far as anyone else can tell
+    pub fn relayed(&self) -> Option<Connect> {
+        let status = self.status.clone().unwrap_or_default();
+        (status.presence != Presence::Invisible).then(|| Connect {
+            user_name: self.user_name.clone(),
+            status: None,
+            admin_token: None,
+        })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -99,3 +206,9 @@ pub struct Disconnect {
     pub user_name: String,
     pub ip: String,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatusUpdate {
+    pub user_name: String,
+    pub status: UserStatus,
+}
@@ -6,26 +6,31 @@ use std::{error::Error, net::SocketAddr, time::Duration};
 use tokio::{
     io::{AsyncBufReadExt, BufReader, split},
     net::TcpStream,
-    sync::mpsc::{Receiver, Sender, channel},
+    sync::mpsc::{Receiver, Sender, UnboundedReceiver, UnboundedSender, channel, unbounded_channel},
 };
 
 pub struct NetworkClient {
-    tx_ui: Sender<MessageType>,
+    // in the order the UI sent them, without ever blocking the UI
+    tx_ui: UnboundedSender<MessageType>,
+    // bulk traffic like attachment uploads, sent only when nothing else is waiting
+    tx_bulk: Sender<MessageType>,
     rx_net: Receiver<MessageType>,
     _handle: tokio::task::JoinHandle<()>,
 }
 
 impl NetworkClient {
-    pub fn connect(socket: SocketAddr, rt_handle: &tokio::runtime::Handle) -> Self {
-        let (tx_ui, rx_ui) = channel::<MessageType>(128);
+    pub fn connect(socket: SocketAddr, rt_handle: &tokio::runtime::Handle, ctx: egui::Context) -> Self {
+        let (tx_ui, rx_ui) = unbounded_channel::<MessageType>();
+        let (tx_bulk, rx_bulk) = channel::<MessageType>(16);
         let (tx_net, rx_net) = channel::<MessageType>(128);
+        let channels = ClientChannels { tx_net, rx_ui, rx_bulk, ctx };
 
         // try to connect to a server
         let handle = rt_handle.spawn(async move {
             match TcpStream::connect(&socket).await {
                 Ok(stream) => {
                     // make the client
-                    if let Err(e) = client(stream, tx_net, rx_ui).await {
+                    if let Err(e) = client(stream, channels).await {
                         eprintln!("Client error: {}", e);
                     }
                 }
@@ -41,7 +46,7 @@ impl NetworkClient {
 
                     // Create client
                     let stream = TcpStream::connect(&socket).await.unwrap();
-                    if let Err(e) = client(stream, tx_net, rx_ui).await {
+                    if let Err(e) = client(stream, channels).await {
                         eprintln!("Client error: {}", e);
                     }
                 }
@@ -49,50 +54,77 @@ impl NetworkClient {
         });
 
         Self {
-            tx_ui: tx_ui,
-            rx_net: rx_net,
+            tx_ui,
+            tx_bulk,
+            rx_net,
             _handle: handle,
         }
     }
 
     // send messages to the network side
-    pub fn send(&self, message: MessageType, rt: &tokio::runtime::Handle) {
-        let tx = self.tx_ui.clone();
-        rt.spawn(async move {
-            if let Err(e) = tx.send(message).await {
-                eprintln!("Error sending message to the network: {e}");
-            }
-        });
+    pub fn send(&self, message: MessageType) {
+        if let Err(e) = self.tx_ui.send(message) {
+            eprintln!("Error sending message to the network: {e}");
+        }
+    }
+
+    // queues bulk traffic without waiting, false when the queue is full
+    pub fn try_send_bulk(&self, message: MessageType) -> bool {
+        self.tx_bulk.try_send(message).is_ok()
+    }
+
+    // false once the connection to the server has been lost
+    pub fn is_connected(&self) -> bool {
+        !self._handle.is_finished()
     }
 
     pub fn recv(&mut self) -> Option<MessageType> {
-        match self.rx_net.try_recv() {
-            Ok(msg) => Some(msg),
-            Err(_) => None,
-        }
+        self.rx_net.try_recv().ok()
     }
+
+    // a client without a connection, the test gets what the UI sends
+    // instead: messages on the first receiver, bulk traffic on the second
+    #[cfg(test)]
+    pub fn detached() -> (Self, UnboundedReceiver<MessageType>, Receiver<MessageType>) {
+        let (tx_ui, rx_ui) = unbounded_channel();
+        let (tx_bulk, rx_bulk) = channel(16);
+        let (_, rx_net) = channel(1);
+        let client = Self { tx_ui, tx_bulk, rx_net, _handle: tokio::spawn(std::future::pending()) };
+        (client, rx_ui, rx_bulk)
+    }
+}
+
+// the ends of the channels the network task uses to talk to the UI
+pub struct ClientChannels {
+    tx_net: Sender<MessageType>,
+    rx_ui: UnboundedReceiver<MessageType>,
+    rx_bulk: Receiver<MessageType>,
+    // repainted when a message arrives so the UI picks it up straight away
+    ctx: egui::Context,
 }
 
 pub async fn client(
-    // socket: &SocketAddr,
     stream: TcpStream,
-    tx_net: Sender<MessageType>,
-    mut rx_ui: Receiver<MessageType>,
+    channels: ClientChannels,
 ) -> Result<(), Box<dyn Error>> {
+    let ClientChannels { tx_net, mut rx_ui, mut rx_bulk, ctx } = channels;
     println!("client created: {:?}", stream);
     let (reader, mut writer) = split(stream);
     let mut reader = BufReader::new(reader);
-    let mut buf = String::new();
+    // read_until rather than read_line, it is safe to cancel inside select!
+    let mut buf = Vec::<u8>::new();
 
     loop {
         tokio::select! {
+            // messages from the UI go ahead of bulk transfers
+            biased;
 
             // recieve from server
-            result = reader.read_line(&mut buf) => {
+            result = reader.read_until(b'\n', &mut buf) => {
                 match result {
                     Ok(0) => break,
                     Ok(_) => {
-                        let deserialized_msg: MessageType = match serde_json::from_str(&buf) {
+                        let deserialized_msg: MessageType = match serde_json::from_slice(&buf) {
                             Ok(msg) => {msg},
                             Err(e) => {
                                 eprintln!("failed to deserialize msg: {}", e);
@@ -102,11 +134,13 @@ pub async fn client(
                         };
 
                         tx_net.send(deserialized_msg).await?;
+                        ctx.request_repaint();
 
                         buf.clear();
                     },
                     Err(e) => {
                         eprintln!("Network read error: {}", e);
+                        break;
                     },
                 }
             }
@@ -127,6 +161,13 @@ pub async fn client(
                     }
                 }
             }
+
+            // bulk transfers from the UI
+            Some(msg) = rx_bulk.recv() => {
+                if let Err(e) = helpers::send_message(&mut writer, msg).await {
+                    eprintln!("error sending message to server: {}", e);
+                };
+            }
         }
     }
 
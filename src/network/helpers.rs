@@ -1,19 +1,6 @@
 // network.rs
-use std::{
-    error::Error,
-    net::{SocketAddr},
-    collections::HashMap,
-    sync::Arc,
-};
-use tokio::{
-    io::{
-        AsyncWrite,
-        AsyncWriteExt,
-        WriteHalf,
-    },
-    net::{TcpStream},
-    sync::Mutex,
-};
+use std::error::Error;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::message::{MessageType};
 
@@ -25,21 +12,3 @@ where W: AsyncWrite + Unpin, {
     writer.write_all(b"\n").await?;
     Ok(())
 }
-
-pub async fn send_to_clients(
-    clients: &mut Arc<Mutex<HashMap<SocketAddr, WriteHalf<TcpStream>>>>,
-    msg: MessageType,
-) -> Result<(), Box<dyn Error + 'static>> {
-    Ok({
-        let mut clients = clients.lock().await;
-
-        for (socket, stream) in clients.iter_mut() {
-            match send_message(stream, msg.clone()).await {
-                Ok(_) => {},
-                Err(e) => {
-                    eprintln!("Error sending message to client {:?}: {}", socket, e);
-                },
-            };
-        }
-    })
-}
\ No newline at end of file
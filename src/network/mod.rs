@@ -1,4 +1,5 @@
 pub mod client;
 pub mod server;
 pub mod helpers;
+pub mod public;
 pub mod state;
\ No newline at end of file
@@ -0,0 +1,157 @@
+// http requests made because of something a peer sent, like a link preview.
+// a peer could use them to reach services on our own network, so they only
+// go to public addresses: names are checked as they are resolved, addresses
+// written in urls before they are fetched and after every redirect
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use reqwest::{
+    Url,
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+
+const MAX_REDIRECTS: usize = 5;
+
+// false for loopback, private, link-local and other addresses that don't
+// lead out to the internet
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            },
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || a == 0
+        // shared by carrier-grade nats
+        || (a == 100 && (64..128).contains(&b)))
+}
+
+// checks a url before it is fetched. names can only be checked once they are
+// resolved, which the client does
+pub fn check_url(url: &Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("unsupported link scheme: {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or("the link has no host")?;
+    if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+        && !is_public_ip(ip)
+    {
+        return Err(format!("{host} is not a public address"));
+    }
+    Ok(())
+}
+
+// resolves names like the system does, leaving out addresses that aren't public
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{host} has no public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn redirect_policy() -> redirect::Policy {
+    redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+// a client for fetches a peer asked for. urls still have to go through
+// check_url first, the client only sees addresses it resolved itself
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect_policy())
+        // a proxy would resolve names itself, out of reach of the check
+        .no_proxy()
+        .build()
+        .expect("could not create http client")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{StubResponse, serve};
+
+    #[test]
+    fn private_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1",
+            "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:192.168.1.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn urls_with_private_addresses_are_refused() {
+        let check = |url: &str| check_url(&Url::parse(url).unwrap());
+        assert!(check("http://127.0.0.1:8080/admin").is_err());
+        assert!(check("http://[::1]/").is_err());
+        assert!(check("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(check("file:///etc/passwd").is_err());
+        assert!(check("https://93.184.216.34/").is_ok());
+        // names are checked when they are resolved
+        assert!(check("https://example.com/").is_ok());
+    }
+
+    #[tokio::test]
+    async fn names_resolving_to_private_addresses_are_refused() {
+        let (addr, log) = serve(vec![("/page", StubResponse::ok("text/html", "hi"))]).await;
+        let url = format!("http://localhost:{}/page", addr.port());
+        assert!(client().get(&url).send().await.is_err());
+        assert!(log.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn redirects_to_private_addresses_are_refused() {
+        let (addr, log) = serve(vec![
+            ("/moved", StubResponse::status(302, "").header("location", "http://127.0.0.1:1/secret")),
+        ])
+        .await;
+        // the stub itself is local, so only the redirect policy is used here
+        let client = reqwest::Client::builder().redirect(redirect_policy()).build().unwrap();
+        let url = format!("http://localhost:{}/moved", addr.port());
+        assert!(client.get(&url).send().await.is_err());
+        assert_eq!(log.lock().unwrap().len(), 1);
+    }
+}
@@ -1,16 +1,102 @@
-use crate::{message::MessageType, room::Room};
-use std::{collections::HashMap, error::Error, net::SocketAddr, sync::Arc};
+use crate::{
+    attachment::{UploadAck, UploadRejected, store::BlobStore, validate},
+    emoji,
+    hash::content_hash,
+    link_preview::{self, LinkPreview, PreviewCache},
+    message::{Avatar, CustomEmoji, CustomEmojiList, Disconnect, MessageType, Notification},
+    network::public,
+    room::Room,
+    sticker::{self, StickerPack},
+    user::presence::Presence,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+};
 use tokio::{
     io::{AsyncBufReadExt, BufReader, ReadHalf},
     net::{TcpListener, TcpStream},
+    sync::Mutex,
 };
 
+// largest profile picture the server will accept
+const MAX_AVATAR_BYTES: usize = 512 * 1024;
+
+// state shared by every room on the server
+#[derive(Default)]
+pub(crate) struct ServerState {
+    http: reqwest::Client,
+    link_previews: Mutex<PreviewCache>,
+    // avatars by content hash, sent to clients when they join
+    avatars: Mutex<HashMap<String, Vec<u8>>>,
+    blobs: Mutex<BlobStore>,
+    // the fetch each client is being sent a blob for, checked before each
+    // chunk goes out. a stream stops once its fetch was cancelled or replaced
+    fetches: Mutex<HashMap<(SocketAddr, String), u64>>,
+    // custom emoji by name, sent to clients when they join
+    custom_emoji: Mutex<BTreeMap<String, CustomEmoji>>,
+    // from CHAT_ADMIN_TOKEN, clients that connect with it are admins
+    admin_token: Option<String>,
+    // clients allowed to add custom emoji
+    admins: Mutex<HashSet<SocketAddr>>,
+    // who each client said they are when they connected, one name each
+    user_names: Mutex<HashMap<SocketAddr, String>>,
+    // packs from SERVER_STICKER_DIR, their images are in the blob store
+    sticker_packs: Vec<StickerPack>,
+}
+
+impl ServerState {
+    async fn is_admin(&self, socket: &SocketAddr) -> bool {
+        self.admins.lock().await.contains(socket)
+    }
+
+    // fetches a preview for a client, failures are cached as empty previews
+    async fn link_preview(&self, url: &str) -> LinkPreview {
+        if let Some(preview) = self.link_previews.lock().await.get(url) {
+            return preview.clone();
+        }
+
+        let preview = match link_preview::fetch(&self.http, url).await {
+            Ok(preview) => preview,
+            Err(e) => {
+                eprintln!("failed to fetch link preview for {url}: {e}");
+                LinkPreview { url: url.to_string(), ..Default::default() }
+            }
+        };
+        self.link_previews.lock().await.insert(preview.clone());
+        preview
+    }
+}
+
 pub async fn server(socket: &SocketAddr) -> Result<(), Box<dyn Error>> {
     let listener = TcpListener::bind(socket).await.unwrap();
     let rooms: Arc<HashMap<&str, Arc<Room>>> = Arc::new(HashMap::from([
         ("main", Arc::new(Room::new("main"))),
         ("general", Arc::new(Room::new("general"))),
     ]));
+    // without a token nobody can add emoji
+    let admin_token = std::env::var("CHAT_ADMIN_TOKEN").ok().filter(|token| !token.is_empty());
+    let mut blobs = BlobStore::default();
+    let sticker_packs = match std::env::var("SERVER_STICKER_DIR") {
+        Ok(dir) => {
+            let (packs, stickers) = sticker::load_packs(std::path::Path::new(&dir));
+            for (_, bytes) in stickers {
+                blobs.insert(bytes);
+            }
+            packs
+        },
+        Err(_) => Vec::new(),
+    };
+    let shared = Arc::new(ServerState {
+        // previews are fetched for any link a client sends
+        http: public::client(),
+        admin_token,
+        sticker_packs,
+        blobs: Mutex::new(blobs),
+        ..Default::default()
+    });
 
     println!("now accepting clients");
 
@@ -23,21 +109,24 @@ pub async fn server(socket: &SocketAddr) -> Result<(), Box<dyn Error>> {
         // send the names of the rooms available to the client
 
         // add them to user list
-        {
-            let mut clients = rooms["main"].client_sockets.lock().await;
-            clients.insert(socket, writer);
-        }
+        rooms["main"].join(socket, writer).await;
 
         // handle the client
         let room_clone = Arc::clone(&rooms["main"]);
+        let shared_clone = Arc::clone(&shared);
         tokio::spawn(async move {
             println!("client {:?} now being SERVED", socket);
-            handle_connection(reader, room_clone).await;
+            handle_connection(reader, socket, room_clone, shared_clone).await;
         });
     }
 }
 
-async fn handle_connection(reader: ReadHalf<TcpStream>, room: Arc<Room>) {
+async fn handle_connection(
+    reader: ReadHalf<TcpStream>,
+    socket: SocketAddr,
+    room: Arc<Room>,
+    shared: Arc<ServerState>,
+) {
     let mut reader = BufReader::new(reader);
     let mut buf: Vec<u8> = Vec::new();
 
@@ -52,33 +141,18 @@ async fn handle_connection(reader: ReadHalf<TcpStream>, room: Arc<Room>) {
                     },
                     // message recieved from a client
                     Ok(_) => {
-                        let msg = std::str::from_utf8(&buf).unwrap();
-                        let deserialized_msg: MessageType = match serde_json::from_str(&msg) {
+                        let deserialized_msg: MessageType = match serde_json::from_slice(&buf) {
                             Ok(msg) => {
                                 msg
                             },
                             Err(e) => {
                                 eprintln!("Error deserializing Message: {e}");
+                                buf.clear();
                                 continue;
                             }
                         };
 
-                        let msg_cpy = deserialized_msg.clone();
-
-                        {
-                            let mut user_list = room.client_names.lock().await;
-                            match deserialized_msg {
-                                MessageType::Connect(m) => {
-                                    user_list.insert(m.user_name, "online".to_string());
-                                },
-                                MessageType::Disconnect(m) => {
-                                    user_list.remove(&m.user_name);
-                                },
-                                _ => {}
-                            }
-                        }
-
-                        room.broadcast(msg_cpy).await;
+                        handle_message(deserialized_msg, socket, &room, &shared).await;
 
                         buf.clear();
                     },
@@ -91,4 +165,419 @@ async fn handle_connection(reader: ReadHalf<TcpStream>, room: Arc<Room>) {
             }
         }
     }
+
+    drop_client(socket, &room, &shared).await;
+}
+
+// forgets a client that has gone, whether it said goodbye or not. its name
+// leaves the user list and is free for someone else to take
+async fn drop_client(socket: SocketAddr, room: &Room, shared: &ServerState) {
+    room.leave(&socket).await;
+    shared.admins.lock().await.remove(&socket);
+    let name = shared.user_names.lock().await.remove(&socket);
+    if let Some(name) = name {
+        room.client_names.lock().await.remove(&name);
+        room.broadcast_user_list().await;
+    }
+}
+
+// takes a name for a client. each client connects once, under a name no
+// other client is using
+fn claim_name(names: &mut HashMap<SocketAddr, String>, socket: SocketAddr, name: &str) -> Result<(), String> {
+    if names.contains_key(&socket) {
+        return Err("you are already connected".to_string());
+    }
+    if name.trim().is_empty() {
+        return Err("the name is empty".to_string());
+    }
+    if names.values().any(|taken| taken == name) {
+        return Err(format!("the name {name} is taken"));
+    }
+    names.insert(socket, name.to_string());
+    Ok(())
+}
+
+// compares hashes so the time taken says nothing about the token
+fn token_matches(expected: Option<&str>, given: Option<&str>) -> bool {
+    match (expected, given) {
+        (Some(expected), Some(given)) => content_hash(expected.as_bytes()) == content_hash(given.as_bytes()),
+        _ => false,
+    }
+}
+
+// the name a message about a user has to carry to come from this client
+fn names_sender(msg: &MessageType) -> Option<&str> {
+    match msg {
+        MessageType::Disconnect(m) => Some(&m.user_name),
+        MessageType::Status(m) => Some(&m.user_name),
+        MessageType::Message(m) => Some(&m.user_name),
+        _ => None,
+    }
+}
+
+async fn handle_message(msg: MessageType, socket: SocketAddr, room: &Arc<Room>, shared: &Arc<ServerState>) {
+    // a client only speaks under the name it connected with
+    if let MessageType::Connect(m) = &msg {
+        let claimed = claim_name(&mut *shared.user_names.lock().await, socket, &m.user_name);
+        if let Err(reason) = claimed {
+            eprintln!("refused connect as {} from {:?}: {reason}", m.user_name, socket);
+            let message = format!("could not join as {}: {reason}", m.user_name);
+            room.send_to(&socket, MessageType::Notification(Notification { message })).await;
+            return;
+        }
+    } else if let Some(name) = names_sender(&msg)
+        && shared.user_names.lock().await.get(&socket).map(String::as_str) != Some(name)
+    {
+        eprintln!("dropped message from {:?} sent as {name}", socket);
+        return;
+    }
+
+    let msg_cpy = msg.clone();
+    let mut roster_changed = true;
+    // invisible users leave as quietly as they joined
+    let mut leaves_quietly = false;
+
+    {
+        let mut user_list = room.client_names.lock().await;
+        match msg {
+            MessageType::Connect(m) => {
+                user_list.insert(m.user_name, m.status.unwrap_or_default());
+            },
+            MessageType::Disconnect(m) => {
+                let status = user_list.remove(&m.user_name);
+                leaves_quietly = status.is_none_or(|s| s.presence == Presence::Invisible);
+            },
+            MessageType::Status(m) => {
+                user_list.insert(m.user_name, m.status);
+            },
+            _ => roster_changed = false,
+        }
+    }
+
+    match msg_cpy {
+        // status changes only go out through the user list so invisible
+        // users are never revealed
+        MessageType::Status(_) => {},
+        // new clients get every known avatar and the custom emoji before
+        // anything else
+        MessageType::Connect(m) => {
+            if token_matches(shared.admin_token.as_deref(), m.admin_token.as_deref()) {
+                shared.admins.lock().await.insert(socket);
+            }
+            let avatars = shared.avatars.lock().await.clone();
+            for (hash, bytes) in avatars {
+                room.send_to(&socket, MessageType::Avatar(Avatar { hash, bytes })).await;
+            }
+            let emoji = shared.custom_emoji.lock().await.values().cloned().collect();
+            let can_add = shared.is_admin(&socket).await;
+            room.send_to(&socket, MessageType::CustomEmojiList(CustomEmojiList { emoji, can_add })).await;
+            if !shared.sticker_packs.is_empty() {
+                room.send_to(&socket, MessageType::StickerPacks(shared.sticker_packs.clone())).await;
+            }
+            if let Some(relayed) = m.relayed() {
+                room.broadcast(MessageType::Connect(relayed)).await;
+            }
+        },
+        // only admins add emoji, adding one under a name that's taken replaces it
+        MessageType::CustomEmoji(custom) => {
+            let checked = if !shared.is_admin(&socket).await {
+                Err("only admins can add emoji".to_string())
+            } else {
+                let known = shared.custom_emoji.lock().await;
+                if known.len() >= emoji::MAX_CUSTOM_EMOJI && !known.contains_key(&custom.name) {
+                    Err(format!("the server already has {} emoji", emoji::MAX_CUSTOM_EMOJI))
+                } else {
+                    emoji::check_custom(&custom)
+                }
+            };
+            if let Err(reason) = checked {
+                eprintln!("rejected emoji :{}: from {:?}: {reason}", custom.name, socket);
+                let message = format!(":{}: was not added: {reason}", custom.name);
+                room.send_to(&socket, MessageType::Notification(Notification { message })).await;
+                return;
+            }
+            shared.custom_emoji.lock().await.insert(custom.name.clone(), custom.clone());
+            room.broadcast(MessageType::CustomEmoji(custom)).await;
+        },
+        MessageType::Avatar(avatar) => {
+            if avatar.bytes.len() > MAX_AVATAR_BYTES
+                || content_hash(&avatar.bytes) != avatar.hash
+                || validate::check_image(&avatar.bytes).is_err()
+            {
+                eprintln!("rejected avatar from {:?}", socket);
+                return;
+            }
+            let is_new = shared
+                .avatars
+                .lock()
+                .await
+                .insert(avatar.hash.clone(), avatar.bytes.clone())
+                .is_none();
+            if is_new {
+                room.broadcast(MessageType::Avatar(avatar)).await;
+            }
+        },
+        // attachments are checked against what was uploaded, the ones that
+        // never made it into the store or fail validation are dropped so
+        // nobody waits for them
+        MessageType::Message(mut m) => {
+            m.room = room.name.clone();
+            {
+                let mut blobs = shared.blobs.lock().await;
+                m.attachments = m
+                    .attachments
+                    .iter()
+                    .filter_map(|a| match blobs.sanitize(a) {
+                        Ok(clean) => Some(clean),
+                        Err(e) => {
+                            eprintln!("dropped attachment {} from {:?}: {e}", a.name, socket);
+                            None
+                        },
+                    })
+                    .collect();
+            }
+            if let Some(gif) = &m.gif
+                && !gif.is_valid()
+            {
+                eprintln!("dropped gif {} from {:?}: not on the provider's media hosts", gif.id, socket);
+                m.gif = None;
+            }
+            // a sticker's image has to be on the server for others to see it,
+            // and goes out without metadata like an attachment
+            if let Some(sticker) = &mut m.sticker {
+                let mut blobs = shared.blobs.lock().await;
+                let clean = blobs.clean_blob(&sticker.hash).ok().filter(|(hash, size)| {
+                    *size as usize <= sticker::MAX_STICKER_BYTES
+                        && blobs.get(hash).is_some_and(|blob| validate::check_image(&blob).is_ok())
+                });
+                match clean {
+                    Some((hash, size)) => {
+                        sticker.hash = hash;
+                        sticker.size = size;
+                    },
+                    _ => {
+                        eprintln!("dropped sticker {} from {:?}: its image was never uploaded", sticker.id, socket);
+                        m.sticker = None;
+                    },
+                }
+            }
+            let has_content = !m.attachments.is_empty() || m.gif.is_some() || m.sticker.is_some();
+            if has_content || !m.message.trim().is_empty() {
+                room.broadcast(MessageType::Message(m)).await;
+            }
+        },
+        MessageType::UploadChunk(chunk) => {
+            let hash = chunk.hash.clone();
+            let result = shared.blobs.lock().await.receive_chunk(chunk);
+            match result {
+                Ok(received) => {
+                    room.send_to(&socket, MessageType::UploadAck(UploadAck { hash, received })).await;
+                },
+                Err(reason) => {
+                    eprintln!("upload of {hash} from {:?} rejected: {reason}", socket);
+                    room.send_to(&socket, MessageType::UploadRejected(UploadRejected { hash, reason })).await;
+                },
+            }
+        },
+        // a resumed upload carries on from whatever the server already has
+        MessageType::UploadResume(hash) => {
+            let received = shared.blobs.lock().await.received(&hash);
+            room.send_to(&socket, MessageType::UploadAck(UploadAck { hash, received })).await;
+        },
+        MessageType::UploadCancel(hash) => {
+            shared.blobs.lock().await.cancel_upload(&hash);
+        },
+        // blobs go out chunk by chunk in the background, so broadcasts to the
+        // room are interleaved with them instead of waiting
+        MessageType::FetchBlob(fetch) => {
+            let key = (socket, fetch.hash.clone());
+            shared.fetches.lock().await.insert(key.clone(), fetch.id);
+            let chunks = shared.blobs.lock().await.download(&fetch.hash, fetch.offset);
+            let room = Arc::clone(room);
+            let shared = Arc::clone(shared);
+            tokio::spawn(async move {
+                match chunks {
+                    Some(chunks) => {
+                        for mut chunk in chunks {
+                            chunk.fetch = fetch.id;
+                            if shared.fetches.lock().await.get(&key) != Some(&fetch.id)
+                                || !room.send_bulk(&socket, MessageType::BlobChunk(chunk)).await
+                            {
+                                break;
+                            }
+                        }
+                        let mut fetches = shared.fetches.lock().await;
+                        if fetches.get(&key) == Some(&fetch.id) {
+                            fetches.remove(&key);
+                        }
+                    },
+                    None => room.send_to(&socket, MessageType::BlobMissing(fetch.hash)).await,
+                }
+            });
+        },
+        MessageType::FetchCancel(hash) => {
+            shared.fetches.lock().await.remove(&(socket, hash));
+        },
+        // fetched in the background so the client's other messages keep flowing
+        MessageType::LinkPreviewRequest(url) => {
+            let room = Arc::clone(room);
+            let shared = Arc::clone(shared);
+            tokio::spawn(async move {
+                let preview = shared.link_preview(&url).await;
+                room.send_to(&socket, MessageType::LinkPreview(preview)).await;
+            });
+        },
+        msg @ MessageType::Notification(_) => room.broadcast(msg).await,
+        // others only need to know who left, not where from
+        MessageType::Disconnect(m) => {
+            if !leaves_quietly {
+                let user_name = m.user_name;
+                room.broadcast(MessageType::Disconnect(Disconnect { user_name, ip: String::new() })).await;
+            }
+        },
+        // these only ever come from the server, a client sending one is
+        // trying to pass it off as the server's
+        MessageType::CustomEmojiList(_)
+        | MessageType::StickerPacks(_)
+        | MessageType::UserList(_)
+        | MessageType::LinkPreview(_)
+        | MessageType::UploadAck(_)
+        | MessageType::UploadRejected(_)
+        | MessageType::BlobChunk(_)
+        | MessageType::BlobMissing(_) => {
+            eprintln!("dropped a message only the server sends from {:?}", socket);
+        },
+    }
+
+    if roster_changed {
+        room.broadcast_user_list().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{message::Connect, user::presence::UserStatus};
+
+    fn socket(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    // joins a client to the room over a real connection, returns its address
+    // and the client's end to read what the server sends it
+    async fn connect(room: &Room) -> (SocketAddr, BufReader<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (stream, socket) = listener.accept().await.unwrap();
+        let (_, writer) = tokio::io::split(stream);
+        room.join(socket, writer).await;
+        (socket, BufReader::new(client))
+    }
+
+    async fn next_message(reader: &mut BufReader<TcpStream>) -> MessageType {
+        let mut line = String::new();
+        tokio::time::timeout(std::time::Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("the server sent nothing")
+            .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn each_client_gets_one_name_nobody_else_has() {
+        let mut names = HashMap::new();
+        assert!(claim_name(&mut names, socket(1), "ann").is_ok());
+        assert!(claim_name(&mut names, socket(2), "ann").is_err());
+        assert!(claim_name(&mut names, socket(1), "bob").is_err());
+        assert!(claim_name(&mut names, socket(2), " ").is_err());
+        assert!(claim_name(&mut names, socket(2), "bob").is_ok());
+        assert_eq!(names[&socket(1)], "ann");
+    }
+
+    #[test]
+    fn admins_need_the_servers_token() {
+        assert!(token_matches(Some("secret"), Some("secret")));
+        assert!(!token_matches(Some("secret"), Some("guess")));
+        assert!(!token_matches(Some("secret"), None));
+        // a server without a token has no admins
+        assert!(!token_matches(None, Some("")));
+        assert!(!token_matches(None, None));
+    }
+
+    #[tokio::test]
+    async fn link_previews_from_clients_are_not_relayed() {
+        let room = Arc::new(Room::new("main"));
+        let shared = Arc::new(ServerState::default());
+        let (sender, _) = connect(&room).await;
+        let (_, mut other) = connect(&room).await;
+
+        let forged = LinkPreview {
+            url: "https://phishing.example".to_string(),
+            title: Some("Your Bank".to_string()),
+            ..Default::default()
+        };
+        handle_message(MessageType::LinkPreview(forged), sender, &room, &shared).await;
+        // what the other client gets next shows the preview was dropped
+        let message = "after".to_string();
+        handle_message(MessageType::Notification(Notification { message }), sender, &room, &shared).await;
+
+        match next_message(&mut other).await {
+            MessageType::Notification(n) => assert_eq!(n.message, "after"),
+            msg => panic!("expected the notification, got {msg:?}"),
+        }
+    }
+    fn connect_as(user_name: &str, presence: Presence) -> MessageType {
+        MessageType::Connect(Connect {
+            user_name: user_name.to_string(),
+            status: Some(UserStatus { presence, text: String::new() }),
+            admin_token: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn clients_that_drop_leave_the_user_list() {
+        let room = Arc::new(Room::new("main"));
+        let shared = Arc::new(ServerState::default());
+        let (ann, _) = connect(&room).await;
+        let (_, mut other) = connect(&room).await;
+        handle_message(connect_as("ann", Presence::Online), ann, &room, &shared).await;
+
+        // gone without a Disconnect
+        drop_client(ann, &room, &shared).await;
+        assert!(room.client_names.lock().await.is_empty());
+        assert!(shared.user_names.lock().await.is_empty());
+        loop {
+            if let MessageType::UserList(list) = next_message(&mut other).await
+                && list.is_empty()
+            {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn invisible_users_leave_quietly() {
+        let room = Arc::new(Room::new("main"));
+        let shared = Arc::new(ServerState::default());
+        let (ann, _) = connect(&room).await;
+        let (bob, _) = connect(&room).await;
+        let (_, mut other) = connect(&room).await;
+        handle_message(connect_as("ann", Presence::Invisible), ann, &room, &shared).await;
+        handle_message(connect_as("bob", Presence::Away), bob, &room, &shared).await;
+
+        for (socket, user_name) in [(ann, "ann"), (bob, "bob")] {
+            let user_name = user_name.to_string();
+            let leave = Disconnect { user_name, ip: "10.0.0.5".to_string() };
+            handle_message(MessageType::Disconnect(leave), socket, &room, &shared).await;
+        }
+
+        // the first leave anyone hears about is bob's, without the address
+        loop {
+            if let MessageType::Disconnect(m) = next_message(&mut other).await {
+                assert_eq!(m.user_name, "bob");
+                assert_eq!(m.ip, "");
+                break;
+            }
+        }
+    }
 }
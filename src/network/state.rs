@@ -4,8 +4,8 @@ use crate::{network::client::NetworkClient, gif};
 
 // handles anything network related
 pub(crate) struct NetworkState {
-    pub(crate) tx: Sender<Vec<gif::Gif>>,
-    pub(crate) rx: Receiver<Vec<gif::Gif>>,
+    pub(crate) tx: Sender<gif::GifEvent>,
+    pub(crate) rx: Receiver<gif::GifEvent>,
     pub(crate) socket_addr: SocketAddr,
     pub(crate) bad_ip_msg: bool,
     pub(crate) ip_str: String,
@@ -1,15 +1,37 @@
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use tokio::{io::WriteHalf, net::TcpStream, sync::Mutex};
+use tokio::{
+    io::{AsyncWriteExt, WriteHalf},
+    net::TcpStream,
+    sync::{
+        Mutex,
+        mpsc::{Receiver, Sender, channel, error::TrySendError},
+    },
+};
 
 use crate::message::MessageType;
-use crate::network::helpers::send_to_clients;
+use crate::user::presence::{self, UserStatus};
+use crate::network::helpers::send_message;
+
+// messages waiting for a client before it counts as too far behind
+const MESSAGE_QUEUE: usize = 1024;
+// blob chunks waiting for a client, downloads wait for room in the queue
+const BULK_QUEUE: usize = 4;
+
+// the queues of one client, drained by its own writer task so a slow client
+// only ever holds up itself
+#[derive(Debug, Clone)]
+pub(crate) struct ClientQueue {
+    messages: Sender<MessageType>,
+    bulk: Sender<MessageType>,
+}
 
 #[derive(Debug, Default)]
+#[allow(dead_code)]
 pub(crate) struct Room {
     pub(crate)name: String,
-    pub(crate)client_sockets: Arc<Mutex<HashMap<SocketAddr, WriteHalf<TcpStream>>>>,
-    pub(crate)client_names: Arc<Mutex<HashMap<String, String>>>,
+    pub(crate)clients: Arc<Mutex<HashMap<SocketAddr, ClientQueue>>>,
+    pub(crate)client_names: Arc<Mutex<HashMap<String, UserStatus>>>,
     pub(crate)messages: Vec<MessageType>,
 }
 
@@ -21,10 +43,80 @@ impl Room {
         }
     }
 
+    // adds a client and starts the task that writes to it
+    pub async fn join(&self, socket: SocketAddr, writer: WriteHalf<TcpStream>) {
+        let (messages, rx_messages) = channel(MESSAGE_QUEUE);
+        let (bulk, rx_bulk) = channel(BULK_QUEUE);
+        tokio::spawn(write_queued(socket, writer, rx_messages, rx_bulk));
+        self.clients.lock().await.insert(socket, ClientQueue { messages, bulk });
+    }
+
+    // the writer task stops once the queued messages are out
+    pub async fn leave(&self, socket: &SocketAddr) {
+        self.clients.lock().await.remove(socket);
+    }
+
+    async fn queue(&self, socket: &SocketAddr) -> Option<ClientQueue> {
+        self.clients.lock().await.get(socket).cloned()
+    }
+
+    // sends the user list to everyone, invisible users appear offline
+    pub async fn broadcast_user_list(&self) {
+        let visible = presence::visible(&*self.client_names.lock().await);
+        self.broadcast(MessageType::UserList(visible)).await;
+    }
+
+    // sends a message to one client in the room
+    pub async fn send_to(&self, socket: &SocketAddr, msg: MessageType) {
+        if let Some(queue) = self.queue(socket).await
+            && queue.messages.send(msg).await.is_err()
+        {
+            eprintln!("Error sending message to client {:?}: it has left", socket);
+        }
+    }
+
+    // queues a blob chunk for one client, waiting while its earlier chunks
+    // are still going out. false once the client has left
+    pub async fn send_bulk(&self, socket: &SocketAddr, msg: MessageType) -> bool {
+        match self.queue(socket).await {
+            Some(queue) => queue.bulk.send(msg).await.is_ok(),
+            None => false,
+        }
+    }
+
+    // queues a message for everyone without waiting on anyone. a client
+    // whose queue is full is disconnected rather than left with gaps
     pub async fn broadcast(&self, msg: MessageType) {
-        let mut clients_clone = Arc::clone(&self.client_sockets);
-        if let Err(e) = send_to_clients(&mut clients_clone, msg).await {
-            eprintln!("Broadcast Error: {e}");
+        self.clients.lock().await.retain(|socket, queue| match queue.messages.try_send(msg.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                eprintln!("Broadcast Error: client {:?} fell too far behind, disconnecting it", socket);
+                false
+            },
+            Err(TrySendError::Closed(_)) => false,
+        });
+    }
+}
+
+// writes a client's queued messages to its socket, blob chunks only when no
+// other message is waiting
+async fn write_queued(
+    socket: SocketAddr,
+    mut writer: WriteHalf<TcpStream>,
+    mut messages: Receiver<MessageType>,
+    mut bulk: Receiver<MessageType>,
+) {
+    loop {
+        let msg = tokio::select! {
+            biased;
+            Some(msg) = messages.recv() => msg,
+            Some(msg) = bulk.recv() => msg,
+            else => break,
+        };
+        if let Err(e) = send_message(&mut writer, msg).await {
+            eprintln!("Error sending message to client {:?}: {}", socket, e);
+            break;
         }
     }
-}
\ No newline at end of file
+    let _ = writer.shutdown().await;
+}
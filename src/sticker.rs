@@ -0,0 +1,211 @@
+// sticker packs, folders of images grouped by subfolder. the server offers
+// the packs in SERVER_STICKER_DIR and each user can add their own from
+// STICKER_DIR. messages refer to a sticker by id, its bytes are a blob the
+// server stores once like an attachment's
+use std::{path::Path, sync::Arc};
+
+use egui::load::SizedTexture;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attachment::{state::Attachments, validate},
+    hash::content_hash,
+    network::client::NetworkClient,
+    ui::texture_cache::TextureCache,
+};
+
+// largest image taken into a pack
+pub const MAX_STICKER_BYTES: usize = 512 * 1024;
+// stickers are shown bigger than other images in the chat, without a frame
+const STICKER_SIZE: f32 = 160.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sticker {
+    // the pack's and the sticker's name, as "pack/name"
+    pub id: String,
+    pub name: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StickerPack {
+    pub name: String,
+    pub stickers: Vec<Sticker>,
+}
+
+// the packs in a folder, one per subfolder, along with the stickers' bytes
+// by hash. images that are too large or unreadable are left out
+pub fn load_packs(dir: &Path) -> (Vec<StickerPack>, Vec<(String, Vec<u8>)>) {
+    let mut packs = Vec::new();
+    let mut blobs = Vec::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        eprintln!("could not read sticker folder {}", dir.display());
+        return (packs, blobs);
+    };
+
+    let mut folders: Vec<_> = entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect();
+    folders.sort();
+    for folder in folders {
+        let pack_name = folder.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let Ok(files) = std::fs::read_dir(&folder) else {
+            continue;
+        };
+        let mut files: Vec<_> = files.flatten().map(|e| e.path()).filter(|p| p.is_file()).collect();
+        files.sort();
+
+        let mut stickers = Vec::new();
+        for file in files {
+            let Ok(bytes) = std::fs::read(&file) else {
+                continue;
+            };
+            if bytes.len() > MAX_STICKER_BYTES || validate::check_image(&bytes).is_err() {
+                eprintln!("skipped sticker {}: not an image or over {} KiB", file.display(), MAX_STICKER_BYTES / 1024);
+                continue;
+            }
+            // stickers are shared, so photos lose their metadata like attachments do
+            let bytes = match validate::sniff(&bytes) {
+                Some(image::ImageFormat::Jpeg) => match validate::strip_jpeg_metadata(&bytes) {
+                    Some(stripped) => stripped,
+                    None => {
+                        eprintln!("skipped sticker {}: the JPEG is malformed", file.display());
+                        continue;
+                    },
+                },
+                _ => bytes,
+            };
+            let name = file.file_stem().unwrap_or_default().to_string_lossy().to_string();
+            let hash = content_hash(&bytes);
+            stickers.push(Sticker {
+                id: format!("{pack_name}/{name}"),
+                name,
+                hash: hash.clone(),
+                size: bytes.len() as u64,
+            });
+            blobs.push((hash, bytes));
+        }
+        if !stickers.is_empty() {
+            packs.push(StickerPack { name: pack_name, stickers });
+        }
+    }
+    (packs, blobs)
+}
+
+// the packs this client can send from
+#[derive(Default)]
+pub(crate) struct Stickers {
+    // offered by the server, replaced on every join
+    pub(crate) server: Vec<StickerPack>,
+    // from STICKER_DIR, the server gets a sticker's bytes when it's sent
+    pub(crate) local: Vec<StickerPack>,
+}
+
+impl Stickers {
+    // reads STICKER_DIR, the stickers' bytes go into the blob cache so they
+    // are shown like any other blob
+    pub fn from_env(attachments: &mut Attachments) -> Self {
+        let Ok(dir) = std::env::var("STICKER_DIR") else {
+            return Self::default();
+        };
+        let (local, blobs) = load_packs(Path::new(&dir));
+        for (hash, bytes) in blobs {
+            attachments.insert(&hash, bytes.into());
+        }
+        Self { server: Vec::new(), local }
+    }
+
+    // every pack, with whether it is a local one
+    pub fn packs(&self) -> impl Iterator<Item = (&StickerPack, bool)> {
+        self.server.iter().map(|p| (p, false)).chain(self.local.iter().map(|p| (p, true)))
+    }
+}
+
+// the sticker's image, asked for from the server the first time it's needed
+pub(crate) fn texture(
+    ui: &egui::Ui,
+    sticker: &Sticker,
+    attachments: &mut Attachments,
+    textures: &mut TextureCache,
+    net: Option<&NetworkClient>,
+) -> Option<SizedTexture> {
+    if let Some(bytes) = attachments.get(&sticker.hash) {
+        return textures.get(ui.ctx(), &sticker.hash, || Some(Arc::clone(&bytes)));
+    }
+    let now = ui.input(|i| i.time);
+    attachments.request_blob(&sticker.hash, &sticker.name, sticker.size, now, net);
+    None
+}
+
+// shows a sticker message, larger than an image and without a card around it
+pub(crate) fn show(
+    ui: &mut egui::Ui,
+    sticker: &Sticker,
+    attachments: &mut Attachments,
+    textures: &mut TextureCache,
+    net: Option<&NetworkClient>,
+) {
+    let size = egui::vec2(STICKER_SIZE, STICKER_SIZE);
+    if let Some(bytes) = attachments.get(&sticker.hash) {
+        match textures.get(ui.ctx(), &sticker.hash, || Some(Arc::clone(&bytes))) {
+            Some(texture) => {
+                ui.add(egui::Image::from_texture(texture).max_size(size)).on_hover_text(&sticker.name);
+            },
+            None => {
+                ui.label(egui::RichText::new(format!("sticker {} (couldn't be shown)", sticker.id)).weak());
+            },
+        }
+        return;
+    }
+
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    if ui.is_rect_visible(rect) {
+        egui::Spinner::new().paint_at(ui, egui::Rect::from_center_size(rect.center(), egui::vec2(24.0, 24.0)));
+        let now = ui.input(|i| i.time);
+        attachments.request_blob(&sticker.hash, &sticker.name, sticker.size, now, net);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{TempDir, encode_image};
+
+    fn png() -> Vec<u8> {
+        encode_image(4, 4, image::ImageFormat::Png)
+    }
+
+    #[test]
+    fn packs_are_read_from_subfolders() {
+        let dir = TempDir::new("stickers");
+        std::fs::create_dir_all(dir.join("cats")).unwrap();
+        std::fs::create_dir_all(dir.join("empty")).unwrap();
+        std::fs::write(dir.join("cats/wave.png"), png()).unwrap();
+        std::fs::write(dir.join("cats/notes.txt"), "not a sticker").unwrap();
+        std::fs::write(dir.join("loose.png"), png()).unwrap();
+
+        let (packs, blobs) = load_packs(dir.path());
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].name, "cats");
+        let wave = &packs[0].stickers[..];
+        assert_eq!(wave.len(), 1);
+        assert_eq!(wave[0].id, "cats/wave");
+        assert_eq!(wave[0].hash, content_hash(&png()));
+        assert_eq!(blobs, [(content_hash(&png()), png())]);
+    }
+
+    #[test]
+    fn jpeg_stickers_lose_their_metadata() {
+        let jpeg = encode_image(4, 4, image::ImageFormat::Jpeg);
+        let exif = [&[0xFF, 0xE1, 0x00, 0x0B][..], b"Exif\0\0GPS"].concat();
+        let photo = [&jpeg[..2], &exif, &jpeg[2..]].concat();
+
+        let dir = TempDir::new("jpeg-stickers");
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        std::fs::write(dir.join("photos/me.jpg"), &photo).unwrap();
+
+        let (packs, blobs) = load_packs(dir.path());
+        assert_eq!(blobs, [(content_hash(&jpeg), jpeg.clone())]);
+        assert_eq!(packs[0].stickers[0].hash, content_hash(&jpeg));
+        assert_eq!(packs[0].stickers[0].size, jpeg.len() as u64);
+    }
+}
@@ -1,120 +1,406 @@
-use std:: {
-    env,
-    error::Error
-};
+use std::{fmt, path::PathBuf, time::Duration};
+use reqwest::{StatusCode, Url, header::RETRY_AFTER};
 use serde::Deserialize;
-use dotenvy;
-use reqwest;
+
+use crate::gif::{
+    disk_cache::DiskCache,
+    provider::{Category, GifInfo, GifProvider, MediaSize, ProviderFuture, ProviderPage},
+};
+
+pub const DEFAULT_BASE_URL: &str = "https://tenor.googleapis.com/v2/";
+// how many trending terms or suggestions to ask for
+const TERMS_LIMIT: u32 = 8;
+// featured gifs and trending terms change through the day, previews don't
+const RESPONSE_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+const PREVIEW_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug)]
+pub enum TenorError {
+    // the request never got an answer
+    Request(reqwest::Error),
+    RateLimited { retry_after: Option<u64> },
+    Http(StatusCode),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TenorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TenorError::Request(e) => write!(f, "could not reach Tenor: {e}"),
+            TenorError::RateLimited { retry_after: Some(secs) } => write!(f, "Tenor is rate limiting us, try again in {secs}s"),
+            TenorError::RateLimited { retry_after: None } => write!(f, "Tenor is rate limiting us, try again later"),
+            TenorError::Http(status) => write!(f, "Tenor answered {status}"),
+            TenorError::Json(e) => write!(f, "unexpected answer from Tenor: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TenorError {}
+
+impl From<reqwest::Error> for TenorError {
+    fn from(e: reqwest::Error) -> Self {
+        TenorError::Request(e)
+    }
+}
+
+impl From<serde_json::Error> for TenorError {
+    fn from(e: serde_json::Error) -> Self {
+        TenorError::Json(e)
+    }
+}
 
 #[derive(Clone)]
 pub struct TenorAPI {
     client: reqwest::Client,
+    // ends in a slash so endpoints can be joined onto it
+    base_url: Url,
     key: String,
     client_key: String,
+    // api answers and previews kept on disk, None to always ask tenor
+    responses: Option<DiskCache>,
+    previews: Option<DiskCache>,
 }
 
 impl TenorAPI {
-    pub fn new() -> Result<TenorAPI, Box<dyn Error>> {
-        let client = reqwest::Client::new(); 
-        dotenvy::dotenv().ok();
-        let key = match env::var("TENOR_API_KEY"){
-            Ok(key) => key,
-            Err(e) => return Err(Box::new(e)),
-        };
+    pub fn new(key: String) -> TenorAPI {
+        Self::with_base_url(key, Url::parse(DEFAULT_BASE_URL).unwrap())
+    }
 
-        Ok (Self {
-            client: client,
-            key: key,
+    // talks to another server speaking the tenor api, like a test stub
+    pub fn with_base_url(key: String, mut base_url: Url) -> TenorAPI {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self {
+            client: reqwest::Client::new(),
+            base_url,
+            key,
             client_key: "Client Key".to_string(),
-        })
+            responses: None,
+            previews: None,
+        }
     }
 
-    // returns a vec of strings to the urls of featured gifs
-    pub async fn featured(&mut self, limit: u32) -> Result<Vec<TenorGif>, Box<dyn Error>>{
-        let mut gif_urls = Vec::<TenorGif>::new();
-        let response = self.client.get(
-            format!(
-                "https://tenor.googleapis.com/v2/featured?key={}&client_key={}&limit={}",
-                self.key, self.client_key, limit
-        ))
-            .send()
-            .await?;
+    // keeps answers and previews under `dir`, dropping whatever has expired
+    pub fn with_cache(mut self, dir: PathBuf) -> Self {
+        let responses = DiskCache::new(dir.join("responses"), RESPONSE_MAX_AGE);
+        let previews = DiskCache::new(dir.join("previews"), PREVIEW_MAX_AGE);
+        responses.prune();
+        previews.prune();
+        self.responses = Some(responses);
+        self.previews = Some(previews);
+        self
+    }
 
-        let text = response.text().await?;
-        // println!("{:?}", text);
-
-        let parsed = serde_json::from_str::<TenorResponse>(&text)?;
-        for result in parsed.results {
-            gif_urls.push( TenorGif {
-                id: result.id.clone(),
-                url: result.media_formats.gif.url.clone(),
-                tinygif_url: result.media_formats.tinygif.url.clone(),
-            });
-        }
-        Ok(gif_urls)
+    // the url of an endpoint with the key and `params` encoded into the query
+    fn endpoint(&self, path: &str, params: &[(&str, &str)]) -> Url {
+        let mut url = self.base_url.join(path).unwrap();
+        url.query_pairs_mut()
+            .append_pair("key", &self.key)
+            .append_pair("client_key", &self.client_key)
+            .extend_pairs(params);
+        url
     }
 
-    pub async fn search(&mut self, search: String, limit: u32) -> Result<Vec<TenorGif>, Box<dyn Error>> {
-        let mut gif_urls = Vec::<TenorGif>::new();
-        let response = self.client.get(
-            format!(
-                "https://tenor.googleapis.com/v2/search?q={}&key={}&client_key={}&limit={}",
-                search, self.key, self.client_key, limit
-            )
-        )
-            .send()
-            .await?;
+    async fn get<T: serde::de::DeserializeOwned>(&self, url: Url) -> Result<T, TenorError> {
+        if let Some(cached) = self.responses.as_ref().and_then(|cache| cache.get(url.as_str()))
+            && let Ok(parsed) = serde_json::from_slice(&cached)
+        {
+            return Ok(parsed);
+        }
+        let response = self.client.get(url.clone()).send().await?;
+
+        match response.status() {
+            StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = response.headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.trim().parse().ok());
+                return Err(TenorError::RateLimited { retry_after });
+            },
+            status if !status.is_success() => return Err(TenorError::Http(status)),
+            _ => {},
+        }
 
         let text = response.text().await?;
-        // println!("{:?}", text);
-
-        let parsed = serde_json::from_str::<TenorResponse>(&text)?;
-        for result in parsed.results {
-            gif_urls.push( TenorGif {
-                id: result.id.clone(),
-                url: result.media_formats.gif.url.clone(),
-                tinygif_url: result.media_formats.tinygif.url.clone()
-            });
+        let parsed = serde_json::from_str(&text)?;
+        if let Some(cache) = &self.responses {
+            cache.put(url.as_str(), text.as_bytes());
         }
-        Ok(gif_urls)
+        Ok(parsed)
+    }
+
+    // a page of featured gifs, `pos` is the cursor from the previous page
+    pub async fn fetch_featured(&self, pos: Option<&str>, limit: u32) -> Result<ProviderPage, TenorError> {
+        let limit = limit.to_string();
+        let mut params = vec![("limit", limit.as_str())];
+        params.extend(pos.map(|pos| ("pos", pos)));
+        self.get_page(self.endpoint("featured", &params)).await
+    }
+
+    pub async fn fetch_search(&self, search: &str, pos: Option<&str>, limit: u32) -> Result<ProviderPage, TenorError> {
+        let limit = limit.to_string();
+        let mut params = vec![("q", search), ("limit", limit.as_str())];
+        params.extend(pos.map(|pos| ("pos", pos)));
+        self.get_page(self.endpoint("search", &params)).await
+    }
+
+    pub async fn categories(&self) -> Result<Vec<Category>, TenorError> {
+        let parsed: CategoriesResponse = self.get(self.endpoint("categories", &[])).await?;
+
+        Ok(parsed.tags
+            .into_iter()
+            .map(|tag| Category {
+                name: tag.name.trim_start_matches('#').to_string(),
+                image: tag.image.map(|image| GifInfo {
+                    id: tag.searchterm.clone(),
+                    url: image.clone(),
+                    preview_url: image,
+                }),
+                query: tag.searchterm,
+            })
+            .collect())
+    }
+
+    pub async fn trending_terms(&self, limit: u32) -> Result<Vec<String>, TenorError> {
+        let limit = limit.to_string();
+        let parsed: TermsResponse = self.get(self.endpoint("trending_terms", &[("limit", &limit)])).await?;
+        Ok(parsed.results)
+    }
+
+    pub async fn autocomplete(&self, prefix: &str, limit: u32) -> Result<Vec<String>, TenorError> {
+        let limit = limit.to_string();
+        let parsed: TermsResponse = self.get(self.endpoint("autocomplete", &[("q", prefix), ("limit", &limit)])).await?;
+        Ok(parsed.results)
+    }
+
+    async fn get_page(&self, url: Url) -> Result<ProviderPage, TenorError> {
+        let parsed: TenorResponse = self.get(url).await?;
+
+        let gifs = parsed.results
+            .into_iter()
+            .map(|result| GifInfo {
+                id: result.id,
+                url: result.media_formats.gif.url,
+                preview_url: result.media_formats.tinygif.url,
+            })
+            .collect();
+        // the last page has an empty cursor
+        let next = parsed.next.filter(|next| !next.is_empty());
+        Ok(ProviderPage { gifs, next })
+    }
+}
+
+impl GifProvider for TenorAPI {
+    fn name(&self) -> &str {
+        "Tenor"
+    }
+
+    fn shareable(&self) -> bool {
+        true
+    }
+
+    fn featured<'a>(&'a self, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        Box::pin(async move { self.fetch_featured(pos, limit).await.map_err(|e| e.to_string()) })
+    }
+
+    fn search<'a>(&'a self, query: &'a str, pos: Option<&'a str>, limit: u32) -> ProviderFuture<'a, ProviderPage> {
+        Box::pin(async move { self.fetch_search(query, pos, limit).await.map_err(|e| e.to_string()) })
+    }
+
+    fn categories(&self) -> ProviderFuture<'_, Vec<Category>> {
+        Box::pin(async move { self.categories().await.map_err(|e| e.to_string()) })
+    }
+
+    fn trending(&self) -> ProviderFuture<'_, Vec<String>> {
+        Box::pin(async move { self.trending_terms(TERMS_LIMIT).await.map_err(|e| e.to_string()) })
+    }
+
+    fn autocomplete<'a>(&'a self, prefix: &'a str) -> ProviderFuture<'a, Vec<String>> {
+        Box::pin(async move { self.autocomplete(prefix, TERMS_LIMIT).await.map_err(|e| e.to_string()) })
+    }
+
+    fn download<'a>(&'a self, gif: &'a GifInfo, size: MediaSize) -> ProviderFuture<'a, Vec<u8>> {
+        let url = match size {
+            MediaSize::Preview => &gif.preview_url,
+            MediaSize::Full => &gif.url,
+        };
+        // only previews are cached, full gifs are downloaded once when sent
+        let cache = self.previews.as_ref().filter(|_| size == MediaSize::Preview);
+        Box::pin(async move {
+            if let Some(bytes) = cache.and_then(|cache| cache.get(url)) {
+                return Ok(bytes);
+            }
+            let resp = self.client.get(url).send().await.map_err(|e| e.to_string())?;
+            let bytes = resp.error_for_status().map_err(|e| e.to_string())?.bytes().await.map_err(|e| e.to_string())?;
+            if let Some(cache) = cache {
+                cache.put(url, &bytes);
+            }
+            Ok(bytes.to_vec())
+        })
     }
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 pub struct TenorResponse {
     results: Vec<TenorResult>,
     next: Option<String>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct TenorResult {
     id: String,
     media_formats: MediaFormats,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct MediaFormats {
     gif: Gif,
     tinygif: TinyGif,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct Gif {
     url: String,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct TinyGif {
     url: String,
 }
 
-pub struct TenorGif {
-    pub id: String,
-    pub url: String,
-    pub tinygif_url: String,
-}
\ No newline at end of file
+#[derive(Debug, Deserialize)]
+struct CategoriesResponse {
+    tags: Vec<CategoryTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CategoryTag {
+    searchterm: String,
+    // "#excited"
+    name: String,
+    image: Option<String>,
+}
+
+// trending terms and autocomplete both answer with a list of searches
+#[derive(Debug, Deserialize)]
+struct TermsResponse {
+    results: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{StubResponse, TempDir, serve};
+    use std::net::SocketAddr;
+
+    const PAGE: &str = r#"{
+        "results": [
+            {"id": "1", "media_formats": {"gif": {"url": "http://x/1.gif"}, "tinygif": {"url": "http://x/1t.gif"}}},
+            {"id": "2", "media_formats": {"gif": {"url": "http://x/2.gif"}, "tinygif": {"url": "http://x/2t.gif"}}}
+        ],
+        "next": "CAgQAA"
+    }"#;
+
+    fn api(addr: SocketAddr) -> TenorAPI {
+        // no trailing slash, it gets added
+        TenorAPI::with_base_url("k3y".to_string(), Url::parse(&format!("http://{addr}/v2")).unwrap())
+    }
+
+    #[tokio::test]
+    async fn pages_are_parsed_with_their_cursor() {
+        let (addr, log) = serve(vec![("/v2/featured", StubResponse::ok("application/json", PAGE))]).await;
+
+        let page = api(addr).fetch_featured(Some("abc"), 2).await.unwrap();
+        assert_eq!(page.gifs.len(), 2);
+        assert_eq!(page.gifs[1].preview_url, "http://x/2t.gif");
+        assert_eq!(page.next.as_deref(), Some("CAgQAA"));
+
+        let request = &log.lock().unwrap()[0];
+        assert!(request.contains("key=k3y"));
+        assert!(request.contains("limit=2"));
+        assert!(request.contains("pos=abc"));
+    }
+
+    #[tokio::test]
+    async fn search_queries_are_encoded() {
+        let last = r#"{"results": [], "next": ""}"#;
+        let (addr, log) = serve(vec![("/v2/search", StubResponse::ok("application/json", last))]).await;
+
+        let page = api(addr).fetch_search("cats & dogs #1", None, 20).await.unwrap();
+        assert!(page.gifs.is_empty());
+        assert_eq!(page.next, None);
+
+        let request = &log.lock().unwrap()[0];
+        assert!(request.contains("q=cats+%26+dogs+%231&"), "{request}");
+        assert!(!request.contains("pos="));
+    }
+
+    #[tokio::test]
+    async fn categories_drop_the_hash() {
+        let body = r##"{"tags": [{"searchterm": "excited", "path": "/v2/search?q=excited", "image": "http://x/e.gif", "name": "#excited"}]}"##;
+        let (addr, _) = serve(vec![("/v2/categories", StubResponse::ok("application/json", body))]).await;
+
+        let categories = api(addr).categories().await.unwrap();
+        assert_eq!(categories[0].name, "excited");
+        assert_eq!(categories[0].query, "excited");
+        assert_eq!(categories[0].image.as_ref().unwrap().url, "http://x/e.gif");
+    }
+
+    #[tokio::test]
+    async fn trending_terms_and_suggestions() {
+        let (addr, log) = serve(vec![
+            ("/v2/trending_terms", StubResponse::ok("application/json", r#"{"results": ["monday", "coffee"]}"#)),
+            ("/v2/autocomplete", StubResponse::ok("application/json", r#"{"results": ["cat", "cat vibing"]}"#)),
+        ]).await;
+        let api = api(addr);
+
+        assert_eq!(api.trending_terms(2).await.unwrap(), ["monday", "coffee"]);
+        assert_eq!(api.autocomplete("ca t", 8).await.unwrap(), ["cat", "cat vibing"]);
+        assert!(log.lock().unwrap()[1].contains("q=ca+t&"));
+    }
+
+    #[tokio::test]
+    async fn answers_and_previews_are_cached() {
+        let (addr, log) = serve(vec![
+            ("/v2/featured", StubResponse::ok("application/json", PAGE)),
+            ("/1t.gif", StubResponse::ok("image/gif", "GIF89a")),
+        ]).await;
+        let dir = TempDir::new("tenor-cache");
+        let api = api(addr).with_cache(dir.path().to_path_buf());
+
+        let first = api.fetch_featured(None, 2).await.unwrap();
+        let again = api.fetch_featured(None, 2).await.unwrap();
+        assert_eq!(first.gifs, again.gifs);
+
+        let preview = GifInfo { preview_url: format!("http://{addr}/1t.gif"), ..first.gifs[0].clone() };
+        for _ in 0..2 {
+            let bytes = GifProvider::download(&api, &preview, MediaSize::Preview).await.unwrap();
+            assert_eq!(bytes, b"GIF89a");
+        }
+
+        // one request each, the second came from disk
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn failures_are_told_apart() {
+        let (addr, _) = serve(vec![
+            ("/v2/featured", StubResponse::status(429, "slow down").header("retry-after", "30")),
+            ("/v2/search", StubResponse::ok("application/json", "<html>")),
+            ("/v2/categories", StubResponse::status(403, "bad key")),
+        ]).await;
+        let api = api(addr);
+
+        assert!(matches!(
+            api.fetch_featured(None, 20).await,
+            Err(TenorError::RateLimited { retry_after: Some(30) })
+        ));
+        assert!(matches!(api.fetch_search("cats", None, 20).await, Err(TenorError::Json(_))));
+        assert!(matches!(api.categories().await, Err(TenorError::Http(StatusCode::FORBIDDEN))));
+
+        // nothing listening
+        let closed = TenorAPI::with_base_url(String::new(), Url::parse("http://127.0.0.1:9/").unwrap());
+        assert!(matches!(closed.categories().await, Err(TenorError::Request(_))));
+    }
+}
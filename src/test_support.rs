@@ -0,0 +1,125 @@
+// helpers shared by tests: a local HTTP stub instead of the internet, temp
+// folders and small encoded images
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::{DynamicImage, ImageFormat};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[derive(Clone)]
+pub struct StubResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl StubResponse {
+    pub fn ok(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self { status: 200, content_type, body: body.into(), headers: Vec::new() }
+    }
+
+    pub fn status(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self { status, content_type: "text/plain", body: body.into(), headers: Vec::new() }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+// the request line ("GET /path?query HTTP/1.1") of every request the stub saw
+pub type RequestLog = Arc<std::sync::Mutex<Vec<String>>>;
+
+// serves fixed responses keyed by path (query string ignored), 404 otherwise
+pub async fn serve(routes: Vec<(&'static str, StubResponse)>) -> (SocketAddr, RequestLog) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let routes: Arc<HashMap<&str, StubResponse>> = Arc::new(routes.into_iter().collect());
+    let log = RequestLog::default();
+    let log_clone = Arc::clone(&log);
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            let routes = Arc::clone(&routes);
+            let log = Arc::clone(&log_clone);
+
+            tokio::spawn(async move {
+                // requests from the tests are small, one read gets the headers
+                let mut buf = vec![0u8; 8192];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                let request_line = request.lines().next().unwrap_or_default().to_string();
+                log.lock().unwrap().push(request_line.clone());
+
+                let target = request_line.split_whitespace().nth(1).unwrap_or("/");
+                let path = target.split('?').next().unwrap_or("/");
+                let resp = routes
+                    .get(path)
+                    .cloned()
+                    .unwrap_or_else(|| StubResponse::status(404, "not found"));
+
+                let extra: String = resp.headers.iter().map(|(name, value)| format!("{name}: {value}\r\n")).collect();
+                let head = format!(
+                    "HTTP/1.1 {} Stub\r\ncontent-type: {}\r\ncontent-length: {}\r\n{}connection: close\r\n\r\n",
+                    resp.status,
+                    resp.content_type,
+                    resp.body.len(),
+                    extra
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&resp.body).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    (addr, log)
+}
+
+// a fresh folder under the system temp dir, removed with everything in it
+// when the guard is dropped, so a failing test doesn't leave it behind
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    // the name only has to be unique among the tests
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("rust-chat-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// a blank image of the given size in the given format
+pub fn encode_image(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let mut out = Cursor::new(Vec::new());
+    DynamicImage::new_rgb8(width, height).write_to(&mut out, format).unwrap();
+    out.into_inner()
+}
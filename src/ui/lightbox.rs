@@ -0,0 +1,204 @@
+// full size image viewer opened by clicking an image in the chat
+use crate::{App, attachment::AttachmentDescriptor, message::MessageType};
+
+const MIN_ZOOM: f32 = 0.05;
+const MAX_ZOOM: f32 = 16.0;
+// scroll distance that doubles the zoom
+const SCROLL_PER_DOUBLING: f32 = 200.0;
+
+pub(crate) struct Lightbox {
+    pub(crate) attachment: AttachmentDescriptor,
+    // None fits the image to the window
+    zoom: Option<f32>,
+    // offset of the image centre from the centre of the view
+    pan: egui::Vec2,
+    // where the image was drawn last frame, for the toolbar's zoom buttons
+    view: egui::Rect,
+}
+
+impl Lightbox {
+    pub fn new(attachment: AttachmentDescriptor) -> Self {
+        Self {
+            attachment,
+            zoom: None,
+            pan: egui::Vec2::ZERO,
+            view: egui::Rect::ZERO,
+        }
+    }
+
+    fn fit(&mut self) {
+        self.zoom = None;
+        self.pan = egui::Vec2::ZERO;
+    }
+
+    // scale that fits `image` into `view`, small images stay at their size
+    fn fit_zoom(image: egui::Vec2, view: egui::Vec2) -> f32 {
+        (view.x / image.x).min(view.y / image.y).min(1.0)
+    }
+
+    fn current_zoom(&self, image: egui::Vec2) -> f32 {
+        self.zoom.unwrap_or_else(|| Self::fit_zoom(image, self.view.size()))
+    }
+
+    // zooms by `factor` keeping the point under `anchor` in place
+    fn zoom_by(&mut self, factor: f32, anchor: egui::Pos2, image: egui::Vec2) {
+        let view = self.view;
+        let current = self.current_zoom(image);
+        let zoom = (current * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let factor = zoom / current;
+
+        let centre = view.center() + self.pan;
+        let new_centre = anchor - (anchor - centre) * factor;
+        self.pan = new_centre - view.center();
+        self.zoom = Some(zoom);
+    }
+}
+
+impl App {
+    // images in the room in the order they were posted, each shown once
+    fn room_images(&self) -> Vec<&AttachmentDescriptor> {
+        let mut images: Vec<&AttachmentDescriptor> = Vec::new();
+        for msg in &self.io.messages {
+            if let MessageType::Message(m) = msg {
+                for attachment in m.attachments.iter().filter(|a| a.is_image()) {
+                    if !images.iter().any(|i| i.hash == attachment.hash) {
+                        images.push(attachment);
+                    }
+                }
+            }
+        }
+        images
+    }
+
+    pub fn lightbox(&mut self, ctx: &egui::Context) {
+        let Some(lightbox) = &self.io.lightbox else {
+            return;
+        };
+        let attachment = lightbox.attachment.clone();
+        let now = ctx.input(|i| i.time);
+        self.attachments.request(&attachment, now, self.network.client.as_ref());
+
+        let images = self.room_images();
+        let index = images.iter().position(|i| i.hash == attachment.hash);
+        let previous = index.and_then(|i| i.checked_sub(1)).and_then(|i| images.get(i)).map(|a| (*a).clone());
+        let next = index.and_then(|i| images.get(i + 1)).map(|a| (*a).clone());
+        let position = index.map(|i| format!("{} / {}", i + 1, images.len()));
+
+        let mut open = !ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Escape));
+        let mut step = None;
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft)) {
+            step = previous.clone();
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight)) {
+            step = next.clone();
+        }
+        let mut save = false;
+
+        // the original if it has arrived, otherwise the thumbnail
+        let texture = [Some(attachment.hash.as_str()), attachment.thumbnail.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|hash| self.textures.get(ctx, hash, || self.attachments.get(hash)));
+        let loading = self.attachments.get(&attachment.hash).is_none();
+        // the thumbnail stands in at the size of the original
+        let image_size = texture.map(|t| match (attachment.width, attachment.height) {
+            (Some(w), Some(h)) => egui::vec2(w as f32, h as f32),
+            _ => t.size,
+        });
+
+        let Some(lightbox) = &mut self.io.lightbox else {
+            return;
+        };
+        egui::Window::new(&attachment.name)
+            .id(egui::Id::new("lightbox"))
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .fixed_size(self.env.window_size * 0.8)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(previous.is_some(), egui::Button::new("◀")).clicked() {
+                        step = previous.clone();
+                    }
+                    if let Some(position) = &position {
+                        ui.label(position);
+                    }
+                    if ui.add_enabled(next.is_some(), egui::Button::new("▶")).clicked() {
+                        step = next.clone();
+                    }
+                    ui.separator();
+
+                    if let Some(image_size) = image_size {
+                        let centre = lightbox.view.center();
+                        if ui.button("-").clicked() {
+                            lightbox.zoom_by(0.8, centre, image_size);
+                        }
+                        ui.label(format!("{:.0}%", lightbox.current_zoom(image_size) * 100.0));
+                        if ui.button("+").clicked() {
+                            lightbox.zoom_by(1.25, centre, image_size);
+                        }
+                    }
+                    if ui.button("fit").clicked() {
+                        lightbox.fit();
+                    }
+                    if ui.button("100%").clicked() {
+                        lightbox.zoom = Some(1.0);
+                        lightbox.pan = egui::Vec2::ZERO;
+                    }
+                    ui.separator();
+                    if ui.button("save as").clicked() {
+                        save = true;
+                    }
+                    if loading {
+                        ui.spinner();
+                    }
+                });
+                ui.separator();
+
+                let (view, resp) = ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
+                lightbox.view = view;
+                let (Some(texture), Some(image_size)) = (texture, image_size) else {
+                    return;
+                };
+
+                if resp.hovered() {
+                    let (scroll, pinch, pointer) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta(), i.pointer.hover_pos()));
+                    let factor = pinch * 2f32.powf(scroll / SCROLL_PER_DOUBLING);
+                    if factor != 1.0 {
+                        lightbox.zoom_by(factor, pointer.unwrap_or(view.center()), image_size);
+                    }
+                }
+                if resp.dragged() {
+                    lightbox.pan += resp.drag_delta();
+                }
+                if resp.double_clicked() {
+                    match lightbox.zoom {
+                        None => lightbox.zoom = Some(1.0),
+                        Some(_) => lightbox.fit(),
+                    }
+                }
+
+                let rect = egui::Rect::from_center_size(
+                    view.center() + lightbox.pan,
+                    image_size * lightbox.current_zoom(image_size),
+                );
+                ui.painter_at(view).image(
+                    texture.id,
+                    rect,
+                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                    egui::Color32::WHITE,
+                );
+            });
+
+        if let Some(attachment) = step {
+            self.io.lightbox = Some(Lightbox::new(attachment));
+        }
+        if save {
+            self.save_attachment(attachment);
+        }
+        if !open {
+            self.io.lightbox = None;
+        }
+    }
+}
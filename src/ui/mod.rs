@@ -1,2 +1,5 @@
+pub(crate) mod lightbox;
 mod panels;
-mod popups;
\ No newline at end of file
+mod popups;
+pub(crate) mod rich_text;
+pub(crate) mod texture_cache;
\ No newline at end of file
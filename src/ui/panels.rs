@@ -1,9 +1,18 @@
-use std::{
-    fs::read, sync::Arc
-};
+use std::{fs::read, path::Path, sync::Arc};
 
 use crate::{
+    attachment::{
+        self, AttachmentDescriptor, LARGE_TRANSFER_BYTES, MAX_ATTACHMENT_BYTES, MAX_STAGED_FILES, PendingFile,
+        state::AttachmentAction, validate,
+    },
+    emoji,
+    gif::GifRef,
+    hash::content_hash,
+    ui::lightbox::Lightbox,
+    mention,
     message::{Message, MessageType},
+    sticker::{self, Sticker},
+    user::presence::{Presence, status_dot},
     App
 };
 
@@ -13,82 +22,557 @@ use egui::{
 };
 use uuid::Uuid;
 
+// width of a file's card in the staging strip
+const STAGED_CARD_WIDTH: f32 = 96.0;
+
+fn composer_id() -> egui::Id {
+    egui::Id::new("composer")
+}
+
+// takes an enter press that sends the message. shift+enter is left for the
+// text box, which starts a new line with it
+fn take_send_key(input: &mut egui::InputState) -> bool {
+    !input.modifiers.shift && input.consume_key(egui::Modifiers::NONE, egui::Key::Enter)
+}
+
 impl App {
     pub fn message_panel(&mut self, ctx: &egui::Context) {
+        self.receive_dropped_files(ctx);
+        self.paste_image(ctx);
+        self.stage_prepared(ctx);
+
         egui::TopBottomPanel::bottom("message_entry").show(ctx, |ui| {
+            self.disconnected_banner(ui);
+            self.transfer_list(ui);
+            self.mention_suggestions(ui);
+            self.shortcode_suggestions(ui);
+            self.staging_strip(ui);
+
             ui.horizontal(|ui| {
-                let text_resp = ui.add(egui::TextEdit::singleline(&mut self.io.message_text)
+                // enter sends, shift+enter starts a new line
+                let composer_id = composer_id();
+                let enter_pressed = ui.memory(|m| m.has_focus(composer_id))
+                    && ui.input_mut(take_send_key);
+
+                let text_resp = ui.add(egui::TextEdit::multiline(&mut self.io.message_text)
+                    .id(composer_id)
                     .desired_width(250.0)
+                    .desired_rows(1)
                     .hint_text("Type Here")
                 );
+                if self.io.focus_composer {
+                    self.io.focus_composer = false;
+                    text_resp.request_focus();
+                }
+                if text_resp.changed()
+                    && let Some(expanded) = emoji::expand_at_end(&self.io.message_text)
+                {
+                    self.io.message_text = expanded;
+                }
                 let send_button_resp = ui.button("send");
-                let image_button_resp= ui.button("add image");
+                let attach_button_resp = ui.button("attach file");
 
-                // image handling
-                if image_button_resp.clicked() {
-                    self.io.file_dialog.pick_file();
+                // attachment handling
+                if attach_button_resp.clicked() {
+                    self.io.file_dialog.pick_multiple();
                 }
 
                 self.io.file_dialog.update(ctx);
 
-                if let Some(path) = self.io.file_dialog.take_picked() {
-                    self.io.image_bytes = read(path.to_str().unwrap()).expect("invalid file path");
+                if let Some(paths) = self.io.file_dialog.take_picked_multiple() {
+                    self.io.attach_error = None;
+                    for path in paths {
+                        self.attach_file(&path);
+                    }
                 }
 
                 // When enter is pressed in text box or send button is pressed
-                if (text_resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)))
-                    || send_button_resp.clicked()
-                {
-                    let time = chrono::Local::now().format("%I:%M %p").to_string();
-                    let message = MessageType::Message(Message {
-                            user_name: self.user.local.name.clone(),
-                            profile_picture: self.user.local.picture.clone(),
-                            message: self.io.message_text.clone(),
-                            image: Arc::new(self.io.image_bytes.clone()),
-                            timestamp: time,
-                            uuid: Uuid::new_v4().to_string(),
-                            uuid_profile_picture: Uuid::new_v4().to_string(),
-                    });
+                if enter_pressed || send_button_resp.clicked() {
+                    self.send_composed();
+                }
 
-                    if let Some(net) = &self.network.client {
-                        net.send(message, &self.rt_handle);
+            });
+
+            if let Some(error) = &self.io.attach_error {
+                ui.colored_label(egui::Color32::DARK_RED, error);
+            }
+        });
+    }
+
+    // staged attachments with a preview, a caption and a remove button each
+    fn staging_strip(&mut self, ui: &mut egui::Ui) {
+        if self.io.preparing.count > 0 {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label(egui::RichText::new(format!("preparing {} file(s)", self.io.preparing.count)).weak());
+            });
+        }
+        if self.io.staged.is_empty() {
+            return;
+        }
+
+        let mut remove = None;
+        egui::ScrollArea::horizontal()
+            .id_salt("staging_strip")
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (i, file) in self.io.staged.iter_mut().enumerate() {
+                        egui::Frame::group(ui.style())
+                            .corner_radius(4)
+                            .show(ui, |ui| {
+                                ui.set_width(STAGED_CARD_WIDTH);
+                                ui.vertical(|ui| {
+                                    ui.horizontal(|ui| {
+                                        ui.add(egui::Label::new(egui::RichText::new(&file.name).small()).truncate());
+                                        if ui.small_button("✖").on_hover_text("remove").clicked() {
+                                            remove = Some(i);
+                                        }
+                                    });
+
+                                    let size = egui::vec2(STAGED_CARD_WIDTH, 64.0);
+                                    let texture = file
+                                        .preview
+                                        .as_deref()
+                                        .and_then(|hash| self.textures.get(ui.ctx(), hash, || Some(file.preview_bytes())));
+                                    match texture {
+                                        Some(texture) => {
+                                            ui.add(egui::Image::from_texture(texture).max_size(size));
+                                        },
+                                        None => {
+                                            ui.label(egui::RichText::new(format!(
+                                                "📎 {}",
+                                                attachment::format_size(file.bytes.len() as u64)
+                                            )).weak());
+                                        },
+                                    }
+
+                                    ui.push_id(i, |ui| {
+                                        ui.add(egui::TextEdit::singleline(&mut file.caption)
+                                            .hint_text("caption")
+                                            .desired_width(STAGED_CARD_WIDTH));
+                                    });
+                                });
+                            });
                     }
-                    
-                    self.io.message_text.clear();
-                    self.io.image_bytes.clear();
+                });
+            });
+
+        if let Some(i) = remove {
+            self.io.staged.remove(i);
+        }
+    }
+
+    // true when the staging strip can't take another file. files still
+    // being prepared count towards the limit too
+    fn staging_full(&mut self) -> bool {
+        let full = self.io.staged.len() + self.io.preparing.count >= MAX_STAGED_FILES;
+        if full {
+            self.io.attach_error = Some(format!("only {MAX_STAGED_FILES} files can be sent at once"));
+        }
+        full
+    }
+
+    // stages the files that finished preparing, checking back while any are left
+    fn stage_prepared(&mut self, ctx: &egui::Context) {
+        while let Some(file) = self.io.preparing.next() {
+            self.io.staged.push(file);
+        }
+        if self.io.preparing.count > 0 {
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+    }
+
+    // reads a picked file into the composer, errors are shown under it
+    fn attach_file(&mut self, path: &Path) {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "file".to_string());
+
+        match read(path) {
+            Ok(bytes) => self.attach_bytes(name, bytes),
+            Err(e) => self.io.attach_error = Some(format!("could not read {name}: {e}")),
+        }
+    }
+
+    pub(crate) fn attach_bytes(&mut self, name: String, bytes: Vec<u8>) {
+        if bytes.len() as u64 > MAX_ATTACHMENT_BYTES {
+            self.io.attach_error = Some(format!(
+                "{name} is too large, attachments are limited to {}",
+                attachment::format_size(MAX_ATTACHMENT_BYTES)
+            ));
+        } else if bytes.is_empty() {
+            self.io.attach_error = Some(format!("{name} is empty"));
+        } else if let Err(e) = validate::check_local_file(&bytes) {
+            self.io.attach_error = Some(format!("{name} can't be sent: {e}"));
+        } else if !self.staging_full() {
+            self.io.preparing.start(&self.rt_handle, name, bytes, self.settings.recompress_images);
+        }
+    }
+
+    // stages files dropped onto the window, and shows a hint while dragging
+    fn receive_dropped_files(&mut self, ctx: &egui::Context) {
+        let (hovering, dropped) = ctx.input(|i| (!i.raw.hovered_files.is_empty(), i.raw.dropped_files.clone()));
+
+        if hovering {
+            let screen = ctx.content_rect();
+            let painter = ctx.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("drop_hint")));
+            painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+            painter.text(
+                screen.center(),
+                egui::Align2::CENTER_CENTER,
+                "drop files to attach them",
+                egui::TextStyle::Heading.resolve(&ctx.style()),
+                egui::Color32::WHITE,
+            );
+        }
+
+        if !dropped.is_empty() {
+            self.io.attach_error = None;
+        }
+        for file in dropped {
+            match (file.path, file.bytes) {
+                (Some(path), _) => self.attach_file(&path),
+                // on the web dropped files only come with their bytes
+                (None, Some(bytes)) => self.attach_bytes(file.name, bytes.to_vec()),
+                (None, None) => {},
+            }
+        }
+    }
+
+    // pastes an image from the clipboard into the composer. egui turns the
+    // shortcut into a paste event only when the clipboard holds text, and on
+    // desktop swallows the key press otherwise, so the release is the signal
+    // there. a press that did come through is used instead of its release
+    fn paste_image(&mut self, ctx: &egui::Context) {
+        let (pressed, released) = ctx.input(|i| {
+            let shortcut = |e: &egui::Event, down: bool| matches!(
+                e,
+                egui::Event::Key { key: egui::Key::V, pressed, modifiers, .. } if modifiers.command && *pressed == down
+            );
+            (
+                i.events.iter().any(|e| shortcut(e, true) || matches!(e, egui::Event::Paste(_))),
+                i.events.iter().any(|e| shortcut(e, false)),
+            )
+        });
+        let pasted = pressed || (released && !self.io.paste_pressed);
+        self.io.paste_pressed = (pressed || self.io.paste_pressed) && !released;
+        if !pasted || !ctx.memory(|m| m.has_focus(composer_id())) {
+            return;
+        }
+
+        let Ok(mut clipboard) = arboard::Clipboard::new() else {
+            return;
+        };
+        // text pastes go to the composer as usual
+        if clipboard.get_text().is_ok_and(|text| !text.is_empty()) {
+            return;
+        }
+        let Ok(image) = clipboard.get_image() else {
+            return;
+        };
+
+        let Some(buffer) = image::RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned()) else {
+            return;
+        };
+        let mut png = std::io::Cursor::new(Vec::new());
+        if let Err(e) = buffer.write_to(&mut png, image::ImageFormat::Png) {
+            self.io.attach_error = Some(format!("could not paste the image: {e}"));
+            return;
+        }
+
+        self.io.attach_error = None;
+        let name = format!("pasted {}.png", chrono::Local::now().format("%Y-%m-%d %H-%M-%S"));
+        self.attach_bytes(name, png.into_inner());
+    }
+
+    // sends the composer contents, attachments are uploaded ahead of the
+    // message that refers to them
+    fn send_composed(&mut self) {
+        let mut attachments = Vec::<AttachmentDescriptor>::new();
+
+        for PendingFile { name, bytes, thumbnail, caption, .. } in std::mem::take(&mut self.io.staged) {
+            let mut descriptor = AttachmentDescriptor::describe(&name, &bytes);
+            descriptor.caption = caption.trim().to_string();
+            if let Some(thumbnail) = thumbnail {
+                let hash = content_hash(&thumbnail);
+                let thumbnail: Arc<[u8]> = thumbnail.into();
+                self.transfers.start_upload(format!("{name} (preview)"), hash.clone(), Arc::clone(&thumbnail));
+                self.attachments.insert(&hash, thumbnail);
+                descriptor.thumbnail = Some(hash);
+            }
+            let bytes: Arc<[u8]> = bytes.into();
+            self.transfers.start_upload(name, descriptor.hash.clone(), Arc::clone(&bytes));
+            // we already have the bytes, no need to download them again
+            self.attachments.insert(&descriptor.hash, bytes);
+            attachments.push(descriptor);
+        }
+
+        let time = chrono::Local::now().format("%I:%M %p").to_string();
+        let message = Message {
+                user_name: self.user.local.name.clone(),
+                avatar: self.user.local.picture_hash.clone(),
+                message: emoji::expand_shortcodes(&self.io.message_text),
+                attachments,
+                timestamp: time,
+                uuid: Uuid::new_v4().to_string(),
+                mentions: mention::resolve(&self.io.message_text, self.user.peers.keys()),
+                ..Default::default()
+        };
+
+        if message.attachments.is_empty() {
+            if let Some(net) = &self.network.client {
+                net.send(MessageType::Message(message));
+            }
+        } else {
+            // the message waits for its uploads, other messages don't
+            self.transfers.queue_message(message);
+        }
+
+        self.io.message_text.clear();
+    }
+
+    // sends a gif by reference as a message of its own
+    pub(crate) fn send_gif(&mut self, gif: GifRef) {
+        let Some(net) = &self.network.client else {
+            return;
+        };
+        let message = Message {
+            user_name: self.user.local.name.clone(),
+            avatar: self.user.local.picture_hash.clone(),
+            message: String::new(),
+            gif: Some(Box::new(gif)),
+            timestamp: chrono::Local::now().format("%I:%M %p").to_string(),
+            uuid: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+        net.send(MessageType::Message(message));
+    }
+
+    // sends a sticker by id as a message of its own. the server has the
+    // images of its own packs, one from a local pack is uploaded first
+    pub(crate) fn send_sticker(&mut self, sticker: Sticker, local: bool) {
+        let message = Message {
+            user_name: self.user.local.name.clone(),
+            avatar: self.user.local.picture_hash.clone(),
+            message: String::new(),
+            sticker: Some(Box::new(sticker.clone())),
+            timestamp: chrono::Local::now().format("%I:%M %p").to_string(),
+            uuid: Uuid::new_v4().to_string(),
+            ..Default::default()
+        };
+
+        if local && let Some(bytes) = self.attachments.get(&sticker.hash) {
+            self.transfers.start_upload(sticker.name, sticker.hash, bytes);
+            self.transfers.queue_message(message);
+        } else if let Some(net) = &self.network.client {
+            net.send(MessageType::Message(message));
+        }
+    }
+
+    // opens the save dialog for an attachment
+    pub(crate) fn save_attachment(&mut self, descriptor: AttachmentDescriptor) {
+        self.io.save_dialog.config_mut().default_file_name = descriptor.name.clone();
+        self.io.save_dialog.save_file();
+        self.io.saving = Some(descriptor);
+    }
+
+    // progress of uploads and large downloads, each can be cancelled
+    fn transfer_list(&mut self, ui: &mut egui::Ui) {
+        let mut cancel_upload = None;
+        for upload in &self.transfers.uploads {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(format!("⬆ {}", upload.name)).weak());
+                ui.add(egui::ProgressBar::new(upload.progress())
+                    .desired_width(150.0)
+                    .text(format!("{} / {}", attachment::format_size(upload.acked), attachment::format_size(upload.total()))));
+                if ui.small_button("✖").on_hover_text("cancel").clicked() {
+                    cancel_upload = Some(upload.hash.clone());
                 }
+            });
+        }
 
+        let mut cancel_download = None;
+        for (hash, download) in self.attachments.downloads() {
+            if download.size < LARGE_TRANSFER_BYTES {
+                continue;
+            }
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(format!("⬇ {}", download.name)).weak());
+                ui.add(egui::ProgressBar::new(download.progress())
+                    .desired_width(150.0)
+                    .text(format!("{} / {}", attachment::format_size(download.received()), attachment::format_size(download.size))));
+                if ui.small_button("✖").on_hover_text("cancel").clicked() {
+                    cancel_download = Some(hash.clone());
+                }
             });
+        }
+
+        let net = self.network.client.as_ref();
+        if let Some(hash) = cancel_upload {
+            self.transfers.cancel(&hash, net);
+        }
+        if let Some(hash) = cancel_download {
+            self.attachments.cancel(&hash, net);
+        }
+    }
+
+    // shown when the connection to the server drops
+    fn disconnected_banner(&mut self, ui: &mut egui::Ui) {
+        if self.network.client.as_ref().is_none_or(|net| net.is_connected()) {
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.colored_label(egui::Color32::DARK_RED, "disconnected from the server");
+            if ui.button("reconnect").clicked() {
+                self.reconnect(ui.ctx());
+            }
+        });
+    }
+
+    // offers roster names while an @mention is being typed in the composer
+    fn mention_suggestions(&mut self, ui: &mut egui::Ui) {
+        let Some((at, prefix)) = mention::partial_at_end(&self.io.message_text) else {
+            return;
+        };
+        let names = mention::suggestions(prefix, self.user.peers.keys());
+        if names.is_empty() {
+            return;
+        }
+
+        ui.horizontal_wrapped(|ui| {
+            for name in names {
+                if ui.small_button(format!("@{name}")).clicked() {
+                    self.io.message_text.truncate(at);
+                    self.io.message_text.push_str(&format!("@{name} "));
+                    self.io.focus_composer = true;
+                }
+            }
+        });
+    }
+
+    // offers emoji while a :shortcode: is being typed in the composer
+    fn shortcode_suggestions(&mut self, ui: &mut egui::Ui) {
+        let Some((colon, prefix)) = emoji::partial_at_end(&self.io.message_text) else {
+            return;
+        };
+        let custom: Vec<String> = self.server_emoji.search(prefix, true).into_iter().map(String::from).collect();
+        let found = emoji::shortcode_suggestions(prefix, 8);
+        if custom.is_empty() && found.is_empty() {
+            return;
+        }
+
+        ui.horizontal_wrapped(|ui| {
+            for name in custom {
+                if ui.small_button(format!(":{name}:")).clicked() {
+                    self.io.message_text.truncate(colon);
+                    self.io.message_text.push_str(&format!(":{name}:"));
+                    self.recent_emoji.record(&format!(":{name}:"));
+                    self.io.focus_composer = true;
+                }
+            }
+            for (code, found) in found {
+                let found = emoji::with_tone(found, self.io.emoji_picker.skin_tone);
+                if ui.small_button(format!("{found} :{code}:")).clicked() {
+                    self.io.message_text.truncate(colon);
+                    self.io.message_text.push_str(found.as_str());
+                    self.recent_emoji.record(found.as_str());
+                    self.io.focus_composer = true;
+                }
+            }
         });
     }
 
     pub fn side_panel(&mut self, ctx: &egui::Context) {
         egui::SidePanel::right("user_panel")
             .resizable(false)
-            .exact_width(100.0)
+            .exact_width(150.0)
             .show(ctx, |ui| {
 
                 ui.vertical(|ui| {
+                    self.status_selector(ui);
+                    ui.separator();
+
                     ui.heading(egui::RichText::new("Users"));
                     ui.separator();
 
-                    for (key, _) in &mut self.user.peers {
-                        ui.label(key);
+                    let mut peers: Vec<_> = self.user.peers.iter().collect();
+                    peers.sort_by(|a, b| a.0.cmp(b.0));
+                    for (name, status) in peers {
+                        ui.horizontal(|ui| {
+                            status_dot(ui, status.presence)
+                                .on_hover_text(status.presence.label());
+                            ui.label(name);
+                        });
+                        if !status.text.is_empty() {
+                            ui.label(egui::RichText::new(&status.text).weak().small());
+                        }
                     }
                 });
 
                 ui.with_layout(Layout::bottom_up(Align::Center), |ui| {
+                    let mentions_button = ui.button(match self.io.mentions.unread() {
+                        0 => "mentions".to_string(),
+                        n => format!("mentions ({n})"),
+                    });
+                    self.mentions_popup(&mentions_button);
+
+                    let settings_button = ui.button("settings");
+                    self.settings_popup(&settings_button);
+
                     let emoji_button = ui.button("emojis");
                     self.emoji_popup(&emoji_button, ui);
 
                     let gif_button = ui.button("GIFs");
                     self.gif_popup(&gif_button, ui);
+
+                    let sticker_button = ui.button("stickers");
+                    self.sticker_popup(&sticker_button);
                 });
 
             });
     }
 
+    // lets the local user pick a presence and set custom status text
+    fn status_selector(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        let current = self.user.local.status.presence;
+
+        ui.horizontal(|ui| {
+            status_dot(ui, current);
+            egui::ComboBox::from_id_salt("status_selector")
+                .selected_text(current.label())
+                .width(110.0)
+                .show_ui(ui, |ui| {
+                    for presence in Presence::ALL {
+                        if ui
+                            .selectable_value(&mut self.user.local.status.presence, presence, presence.label())
+                            .changed()
+                        {
+                            changed = true;
+                        }
+                    }
+                });
+        });
+
+        let text_resp = ui.add(
+            egui::TextEdit::singleline(&mut self.user.local.status.text)
+                .hint_text("Set a status")
+                .desired_width(f32::INFINITY),
+        );
+        if text_resp.lost_focus() {
+            changed = true;
+        }
+
+        if changed {
+            self.user.auto_away = false;
+            self.send_status();
+        }
+    }
+
     pub fn chat_panel(&mut self, ctx: &egui::Context) {
+        let mut attachment_action = None;
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Chat Room");
             ui.separator();
@@ -98,17 +582,120 @@ impl App {
             .show(ui, |ui| {
                 for msg in self.io.messages.iter_mut() {
                     match msg {
-                        MessageType::Message(msg) => {ui.add(msg);},
+                        MessageType::Message(msg) => {
+                            let avatar = self.textures.get(ctx, &msg.avatar, || self.user.avatars.get(&msg.avatar).cloned());
+                            let mut custom_emoji = |ctx: &egui::Context, name: &str| {
+                                let image = self.server_emoji.get(name)?;
+                                self.textures.get(ctx, &image.hash, || Some(Arc::clone(&image.bytes)))
+                            };
+                            if msg.user_name != self.user.local.name
+                                && msg.mentions_user(&self.user.local.name)
+                            {
+                                egui::Frame::new()
+                                    .fill(ui.visuals().warn_fg_color.gamma_multiply(0.15))
+                                    .corner_radius(4)
+                                    .inner_margin(4)
+                                    .show(ui, |ui| msg.show(ui, avatar, &mut custom_emoji));
+                            } else {
+                                msg.show(ui, avatar, &mut custom_emoji);
+                            }
+
+                            if let Some(action) = self.attachments.show(
+                                ui,
+                                &msg.attachments,
+                                &mut self.textures,
+                                self.network.client.as_ref(),
+                            ) {
+                                attachment_action = Some(action);
+                            }
+                            if let Some(gif) = &msg.gif {
+                                self.gif_media.show(ui, gif, &mut self.textures, &self.rt_handle);
+                            }
+                            if let Some(sticker) = &msg.sticker {
+                                sticker::show(
+                                    ui,
+                                    sticker,
+                                    &mut self.attachments,
+                                    &mut self.textures,
+                                    self.network.client.as_ref(),
+                                );
+                            }
+
+                            self.link_previews.show_cards(
+                                ui,
+                                &msg.links(),
+                                &mut self.textures,
+                                self.settings.link_previews,
+                                self.network.client.as_ref(),
+                                &self.rt_handle,
+                            );
+                        },
                         MessageType::Notification(msg) => {ui.add(msg);},
                         MessageType::Connect(_) => {},
+                        MessageType::Avatar(_) => {},
+                        MessageType::CustomEmojiList(_) => {},
+                        MessageType::CustomEmoji(_) => {},
+                        MessageType::StickerPacks(_) => {},
                         MessageType::UserList(msg) => {
                             self.user.peers = msg.clone();
                         },
                         MessageType::Disconnect(_) => {},
+                        MessageType::Status(_) => {},
+                        MessageType::LinkPreviewRequest(_) => {},
+                        MessageType::LinkPreview(_) => {},
+                        MessageType::UploadChunk(_) => {},
+                        MessageType::UploadAck(_) => {},
+                        MessageType::UploadRejected(_) => {},
+                        MessageType::UploadResume(_) => {},
+                        MessageType::UploadCancel(_) => {},
+                        MessageType::FetchBlob(_) => {},
+                        MessageType::FetchCancel(_) => {},
+                        MessageType::BlobChunk(_) => {},
+                        MessageType::BlobMissing(_) => {},
                     }
                 }
             });
         });
+
+        match attachment_action {
+            Some(AttachmentAction::Save(descriptor)) => self.save_attachment(descriptor),
+            Some(AttachmentAction::Open(descriptor)) => self.io.lightbox = Some(Lightbox::new(descriptor)),
+            None => {},
+        }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // runs a frame with enter pressed, returning whether it sends and whether
+    // the press is still there for the text box
+    fn press_enter(modifiers: egui::Modifiers) -> (bool, bool) {
+        let ctx = egui::Context::default();
+        let input = egui::RawInput {
+            modifiers,
+            events: vec![egui::Event::Key {
+                key: egui::Key::Enter,
+                physical_key: None,
+                pressed: true,
+                repeat: false,
+                modifiers,
+            }],
+            ..Default::default()
+        };
+        let mut result = (false, false);
+        let _ = ctx.run(input, |ctx| {
+            let sent = ctx.input_mut(take_send_key);
+            result = (sent, ctx.input(|i| i.key_pressed(egui::Key::Enter)));
+        });
+        result
+    }
+
+    #[test]
+    fn enter_sends_and_shift_enter_starts_a_new_line() {
+        assert_eq!(press_enter(egui::Modifiers::NONE), (true, false));
+        assert_eq!(press_enter(egui::Modifiers::SHIFT), (false, true));
+    }
+}
@@ -1,40 +1,280 @@
-use crate::App;
+use crate::{
+    App,
+    emoji::{self, PickerTab},
+    gif::{provider::{GifInfo, MediaSize}, saved::SavedGif},
+    link_preview::PreviewMode,
+    sticker,
+};
 use egui::vec2;
+use std::sync::Arc;
 use crate::gif;
 
+// width of a category tile in the gif popup
+const CATEGORY_TILE_SIZE: f32 = 100.0;
+// size of the emoji picker and of the emoji in it
+const EMOJI_POPUP_WIDTH: f32 = 300.0;
+const EMOJI_POPUP_HEIGHT: f32 = 260.0;
+const EMOJI_SIZE: f32 = 22.0;
+// size of a sticker in the sticker popup
+const STICKER_TILE_SIZE: f32 = 72.0;
+
 impl App {
     #[allow(unused_variables)]
     pub fn emoji_popup(&mut self, resp: &egui::Response, ui: &mut egui::Ui) {
-        egui::Popup::menu(&resp)
+        egui::Popup::menu(resp)
             .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
-            .width(50.0)
+            .width(EMOJI_POPUP_WIDTH)
             .show(|ui| {
-
                 ui.heading("emojis");
-                
+
+                let picker = &mut self.io.emoji_picker;
+                ui.add(egui::TextEdit::singleline(&mut picker.search)
+                    .hint_text("Search emoji")
+                    .desired_width(f32::INFINITY));
+
+                // skin tone for the emoji that have them
+                ui.horizontal(|ui| {
+                    let hand = emojis::get("✋").expect("✋ is an emoji");
+                    for tone in emoji::SKIN_TONES {
+                        let text = egui::RichText::new(emoji::with_tone(hand, tone).as_str()).size(16.0);
+                        ui.selectable_value(&mut picker.skin_tone, tone, text);
+                    }
+                });
+
+                // category tabs, hidden while searching
+                let searching = !picker.search.trim().is_empty();
+                if !searching {
+                    ui.horizontal_wrapped(|ui| {
+                        for group in emojis::Group::iter() {
+                            let (icon, name) = emoji::group_label(group);
+                            let tab = PickerTab::Unicode(group);
+                            ui.selectable_value(&mut picker.tab, tab, egui::RichText::new(icon).size(16.0))
+                                .on_hover_text(name);
+                        }
+                        if !self.server_emoji.images.is_empty() || self.server_emoji.can_add {
+                            ui.selectable_value(&mut picker.tab, PickerTab::Server, "server")
+                                .on_hover_text("This server's own emoji");
+                        }
+                    });
+                }
+                ui.separator();
+
+                let tone = picker.skin_tone;
+                let tab = picker.tab;
+                let query = picker.search.clone();
+                let mut picked = None;
+                if !searching && !self.recent_emoji.emoji.is_empty() {
+                    ui.label(egui::RichText::new("recently used").weak().small());
+                    ui.horizontal_wrapped(|ui| {
+                        for recent in self.recent_emoji.emoji.clone() {
+                            // custom emoji are kept as their :name:
+                            let custom = recent.strip_prefix(':').and_then(|r| r.strip_suffix(':'));
+                            let clicked = match custom {
+                                Some(name) if self.server_emoji.get(name).is_some() => self.custom_emoji_button(ui, name),
+                                _ => ui.button(egui::RichText::new(&recent).size(EMOJI_SIZE)).clicked(),
+                            };
+                            if clicked {
+                                picked = Some(recent);
+                            }
+                        }
+                    });
+                    ui.separator();
+                }
+
+                let custom: Vec<String> = if searching {
+                    self.server_emoji.search(&query, false).into_iter().map(String::from).collect()
+                } else if tab == PickerTab::Server {
+                    self.server_emoji.images.keys().cloned().collect()
+                } else {
+                    Vec::new()
+                };
+                let unicode: Vec<&'static emojis::Emoji> = match tab {
+                    _ if searching => emoji::search(&query),
+                    PickerTab::Unicode(group) => group.emojis().collect(),
+                    PickerTab::Server => Vec::new(),
+                };
+                let adding = tab == PickerTab::Server && !searching && self.server_emoji.can_add;
                 egui::ScrollArea::vertical()
-                .auto_shrink(false)
-                .show(ui, |ui| {
-                
-                    egui::Grid::new("emoji_board")
-                        .spacing(vec2(1.0, 1.0))
-                        .show(ui, |ui| {
-                            for i in 1..75 {
-                                
-                                if i % 3 == 1 {
-                                    ui.end_row();
+                    .max_height(EMOJI_POPUP_HEIGHT)
+                    .auto_shrink([false, true])
+                    .show(ui, |ui| {
+                        if custom.is_empty() && unicode.is_empty() {
+                            ui.label(egui::RichText::new("No emoji found").weak());
+                        }
+                        ui.horizontal_wrapped(|ui| {
+                            ui.spacing_mut().item_spacing = vec2(1.0, 1.0);
+                            for name in custom {
+                                if self.custom_emoji_button(ui, &name) {
+                                    picked = Some(format!(":{name}:"));
                                 }
-                                
-                                let emoji = char::from_u32(0x1F600+i).unwrap();
-                                let button_text = egui::RichText::new(emoji.to_string())
-                                    .size(30.0);
-                                if ui.button(button_text).clicked() {
-                                    self.io.message_text.push(emoji);
+                            }
+                            for found in unicode {
+                                let found = emoji::with_tone(found, tone);
+                                let button = ui.button(egui::RichText::new(found.as_str()).size(EMOJI_SIZE))
+                                    .on_hover_text(emoji::hover_text(found));
+                                if button.clicked() {
+                                    picked = Some(found.to_string());
                                 }
                             }
+                        });
+
+                        // admins add emoji from here
+                        if adding {
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.add(egui::TextEdit::singleline(&mut self.io.emoji_picker.new_name)
+                                    .hint_text("name")
+                                    .desired_width(120.0));
+                                if ui.button("add emoji…").clicked() {
+                                    self.io.emoji_dialog.pick_file();
+                                }
+                            });
+                        }
                     });
+
+                if let Some(picked) = picked {
+                    self.io.message_text.push_str(&picked);
+                    self.recent_emoji.record(&picked);
+                    self.io.focus_composer = true;
+                }
+            });
+    }
+
+    // a server emoji in the picker, shown by name until its image is ready
+    fn custom_emoji_button(&mut self, ui: &mut egui::Ui, name: &str) -> bool {
+        let texture = self.server_emoji
+            .get(name)
+            .and_then(|image| self.textures.get(ui.ctx(), &image.hash, || Some(Arc::clone(&image.bytes))));
+        let button = match texture {
+            Some(texture) => {
+                let image = egui::Image::from_texture(texture).fit_to_exact_size(vec2(EMOJI_SIZE, EMOJI_SIZE));
+                ui.add(egui::Button::image(image))
+            },
+            None => ui.button(format!(":{name}:")),
+        };
+        button.on_hover_text(format!(":{name}:")).clicked()
+    }
+
+    pub fn sticker_popup(&mut self, resp: &egui::Response) {
+        egui::Popup::menu(resp)
+            .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+            .width(EMOJI_POPUP_WIDTH)
+            .show(|ui| {
+                ui.heading("stickers");
+                if self.stickers.packs().next().is_none() {
+                    ui.label("No sticker packs. The server offers the folders in SERVER_STICKER_DIR, set STICKER_DIR for your own.");
+                    return;
+                }
+
+                // one tab per pack, local ones are marked
+                ui.horizontal_wrapped(|ui| {
+                    for (i, (pack, local)) in self.stickers.packs().enumerate() {
+                        let tab = ui.selectable_value(&mut self.io.sticker_pack, i, &pack.name);
+                        if local {
+                            tab.on_hover_text("from your STICKER_DIR");
+                        }
+                    }
                 });
-        });
+                ui.separator();
+
+                let Some((pack, local)) = self.stickers
+                    .packs()
+                    .nth(self.io.sticker_pack)
+                    .or_else(|| self.stickers.packs().next())
+                    .map(|(pack, local)| (pack.stickers.clone(), local))
+                else {
+                    return;
+                };
+                let mut picked = None;
+                egui::ScrollArea::vertical()
+                    .max_height(EMOJI_POPUP_HEIGHT)
+                    .auto_shrink([false, true])
+                    .show(ui, |ui| {
+                        ui.horizontal_wrapped(|ui| {
+                            for item in pack {
+                                let texture = sticker::texture(
+                                    ui,
+                                    &item,
+                                    &mut self.attachments,
+                                    &mut self.textures,
+                                    self.network.client.as_ref(),
+                                );
+                                let tile = vec2(STICKER_TILE_SIZE, STICKER_TILE_SIZE);
+                                let button = match texture {
+                                    Some(texture) => ui.add(egui::Button::image(
+                                        egui::Image::from_texture(texture).fit_to_exact_size(tile),
+                                    )),
+                                    None => ui.add_sized(tile, egui::Button::new(&item.name)),
+                                };
+                                if button.on_hover_text(&item.name).clicked() {
+                                    picked = Some(item);
+                                }
+                            }
+                        });
+                    });
+
+                if let Some(picked) = picked {
+                    self.send_sticker(picked, local);
+                }
+            });
+    }
+
+    pub fn settings_popup(&mut self, resp: &egui::Response) {
+        egui::Popup::menu(resp)
+            .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+            .width(200.0)
+            .show(|ui| {
+                ui.heading("settings");
+
+                ui.label("Link previews: ");
+                for mode in PreviewMode::ALL {
+                    ui.radio_value(&mut self.settings.link_previews, mode, mode.label());
+                }
+
+                ui.separator();
+                ui.checkbox(&mut self.settings.recompress_images, "Shrink large images before sending");
+                ui.checkbox(&mut self.settings.gifs_by_reference, "Send GIFs as links")
+                    .on_hover_text("Others download them from the GIF provider. Turn off when they can't reach it, the GIF is sent as a file instead");
+            });
+    }
+
+    pub fn mentions_popup(&mut self, resp: &egui::Response) {
+        // opening the inbox counts as looking at it
+        if resp.clicked() {
+            self.io.mentions.mark_seen();
+        }
+
+        egui::Popup::menu(resp)
+            .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+            .width(250.0)
+            .show(|ui| {
+                ui.heading("mentions");
+
+                egui::ScrollArea::vertical()
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        if self.io.mentions.entries.is_empty() {
+                            ui.label(egui::RichText::new("nobody has mentioned you yet").weak());
+                        }
+
+                        // newest first, unread entries are marked
+                        let new_from = self.io.mentions.new_from;
+                        for (i, entry) in self.io.mentions.entries.iter().enumerate().rev() {
+                            ui.horizontal(|ui| {
+                                if i >= new_from {
+                                    ui.label(egui::RichText::new("new").strong().color(ui.visuals().warn_fg_color));
+                                }
+                                ui.label(egui::RichText::new(&entry.user_name).strong().italics());
+                                if !entry.room.is_empty() {
+                                    ui.label(egui::RichText::new(format!("in #{}", entry.room)).weak());
+                                }
+                                ui.label(egui::RichText::new(&entry.timestamp).weak().italics());
+                            });
+                            ui.label(&entry.message);
+                            ui.separator();
+                        }
+                    });
+            });
     }
 
     #[allow(unused_variables)]
@@ -42,19 +282,30 @@ impl App {
         
         // load featured gifs by default
         if resp.clicked() {
-            self.fetch_featured_gifs();
+            if self.gifs.gifs.is_empty() {
+                self.gifs.reset(String::new());
+                self.fetch_gifs();
+            }
+            if self.io.gif_browse.categories.is_none() {
+                self.fetch_gif_extras();
+            }
         }
 
         // get response from api
-        match self.network.rx.try_recv() {
-            Ok(resp) => {
-                for result in resp {
-                    let k = result.id.clone();
-                    let v = result.clone();
-                    self.gif_cache.insert(k, v);
-                }
-            },
-            Err(_) => {
+        while let Ok(event) = self.network.rx.try_recv() {
+            let browse = &mut self.io.gif_browse;
+            match event {
+                gif::GifEvent::Page(page) => self.gifs.append(page),
+                gif::GifEvent::Categories(categories) => browse.categories = categories.ok(),
+                gif::GifEvent::CategoryImage { query, gif } => {
+                    browse.category_images.insert(query, gif);
+                },
+                gif::GifEvent::Trending(terms) => browse.trending = terms,
+                gif::GifEvent::Suggestions { prefix, terms } => browse.suggestions = (prefix, terms),
+                gif::GifEvent::Inline { name, bytes } => match bytes {
+                    Ok(bytes) => self.attach_bytes(name, bytes),
+                    Err(e) => self.io.attach_error = Some(format!("couldn't download {name}: {e}")),
+                },
             }
         }
 
@@ -63,124 +314,346 @@ impl App {
         let width = self.env.window_size.x / 2.0;
 
         // render the popup
-        egui::Popup::menu(&resp)
+        egui::Popup::menu(resp)
             .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
             .show(|ui| {
                 ui.heading("gifs");
-                egui::ScrollArea::vertical()
-                    .max_width(width)
-                    .max_height(height)
-                    .auto_shrink(false)
-                    .show(ui, |ui| {
-                        ui.label("Search: ");
-                        ui.text_edit_singleline(&mut self.io.gif_search_text);
+                ui.horizontal(|ui| {
+                    for tab in gif::GifTab::ALL {
+                        ui.selectable_value(&mut self.io.gif_browse.tab, tab, tab.label());
+                    }
+                });
+                ui.separator();
 
-                        if ui.button("search").clicked() {
-                            self.gif_cache.clear();
-                            self.fetch_search_gifs();
-                        }
-                        
-                        ui.separator();
-
-                        // show loaded gifs
-                        for (id, gif) in &self.gif_cache {
-                            let image = egui::Image::new(gif.tinygif_url.clone())
-                                .fit_to_original_size(0.50)
-                                .corner_radius(5);
-                            let button = egui::Button::image(image);
-                            let button_resp = ui.add(button);                            
-                            
-                            // if button clicked, send the image
-                            if button_resp.clicked() {
-                                self.io.image_bytes = gif.gif_bytes.clone();
+                match self.io.gif_browse.tab {
+                    gif::GifTab::Browse => self.gif_browse_tab(ui, width, height),
+                    gif::GifTab::Favourites => self.saved_gif_tab(ui, false, width, height),
+                    gif::GifTab::Recent => self.saved_gif_tab(ui, true, width, height),
+                }
+            });
+    }
+
+    // search, categories and the provider's gifs
+    fn gif_browse_tab(&mut self, ui: &mut egui::Ui, width: f32, height: f32) {
+        let Some(provider) = &self.gif_provider else {
+            ui.label("No GIF provider is set up. Set TENOR_API_KEY, or GIF_DIR to a folder of GIFs.");
+            return;
+        };
+        let (provider_name, shareable) = (provider.name().to_string(), provider.shareable());
+        ui.label(egui::RichText::new(format!("from {provider_name}")).weak().small());
+
+        let mut search = None;
+        ui.horizontal(|ui| {
+            ui.label("Search: ");
+            let edit = ui.text_edit_singleline(&mut self.io.gif_search_text);
+            if edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                search = Some(self.io.gif_search_text.trim().to_string());
+            }
+            if ui.button("search").clicked() {
+                search = Some(self.io.gif_search_text.trim().to_string());
+            }
+        });
+
+        // suggestions are asked for once typing pauses
+        let typed = self.io.gif_search_text.trim().to_string();
+        let now = ui.input(|i| i.time);
+        let browse = &mut self.io.gif_browse;
+        browse.autocomplete.update(&typed, now);
+        if let Some(prefix) = browse.autocomplete.settled(now, gif::AUTOCOMPLETE_DELAY_SECS)
+            && !prefix.is_empty()
+        {
+            self.fetch_gif_suggestions(prefix);
+        } else if self.io.gif_browse.autocomplete.is_pending() {
+            ui.ctx().request_repaint_after(std::time::Duration::from_secs_f64(gif::AUTOCOMPLETE_DELAY_SECS));
+        }
+
+        let browse = &self.io.gif_browse;
+        let (prefix, suggestions) = &browse.suggestions;
+        // while typing, completions of the text. before, what's trending
+        let (label, terms) = if typed.is_empty() {
+            ("trending", &browse.trending)
+        } else if *prefix == typed && typed != self.gifs.query {
+            ("suggestions", suggestions)
+        } else {
+            ("", &Vec::new())
+        };
+        if !terms.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(egui::RichText::new(label).weak().small());
+                for term in terms {
+                    if ui.small_button(term).clicked() {
+                        search = Some(term.clone());
+                    }
+                }
+            });
+        }
+        if let Some(query) = search {
+            self.io.gif_search_text = query.clone();
+            self.gifs.reset(query);
+            self.fetch_gifs();
+        }
+        
+        ui.separator();
+
+        egui::ScrollArea::vertical()
+            .max_width(width)
+            .max_height(height)
+            .auto_shrink(false)
+            .show(ui, |ui| {
+                // categories are offered until something is searched for
+                let mut search = None;
+                if self.gifs.query.is_empty()
+                    && let Some(categories) = &self.io.gif_browse.categories
+                    && !categories.is_empty()
+                {
+                    ui.horizontal_wrapped(|ui| {
+                        for category in categories {
+                            let image = self.io.gif_browse.category_images
+                                .get(&category.query)
+                                .and_then(|gif| self.textures.get(ui.ctx(), &gif.hash, || Some(&gif.preview_bytes)));
+                            let tile = match image {
+                                Some(texture) => egui::Button::image_and_text(
+                                    egui::Image::from_texture(texture)
+                                        .fit_to_exact_size(egui::vec2(CATEGORY_TILE_SIZE, CATEGORY_TILE_SIZE * 0.6))
+                                        .corner_radius(5),
+                                    &category.name,
+                                ),
+                                None => egui::Button::new(&category.name),
+                            };
+                            if ui.add(tile).clicked() {
+                                search = Some(category.query.clone());
                             }
                         }
                     });
+                    ui.separator();
+                }
+                if let Some(query) = search {
+                    self.io.gif_search_text = query.clone();
+                    self.gifs.reset(query);
+                    self.fetch_gifs();
+                }
+
+                // show loaded gifs
+                let mut action = None;
+                ui.horizontal_wrapped(|ui| {
+                    for gif in &self.gifs.gifs {
+                        let Some(texture) = self.textures.get(ui.ctx(), &gif.hash, || Some(&gif.preview_bytes)) else {
+                            continue;
+                        };
+                        let saved = SavedGif {
+                            gif: gif::GifRef::new(&provider_name, gif),
+                            shareable,
+                            preview_hash: gif.hash.clone(),
+                        };
+                        let favourite = self.saved_gifs.is_favourite(&saved.gif);
+                        if let Some(clicked) = gif_tile(ui, texture, favourite) {
+                            action = Some((clicked, saved, gif.preview_bytes.clone()));
+                        }
+                    }
+                });
+                match action {
+                    Some((GifTileAction::Pick, saved, preview)) => self.pick_gif(saved, Some(preview)),
+                    Some((GifTileAction::Star, saved, preview)) => self.saved_gifs.toggle_favourite(saved, Some(&preview)),
+                    None => {},
+                }
+                if self.gifs.failed > 0 {
+                    ui.label(egui::RichText::new(format!("{} gifs couldn't be loaded", self.gifs.failed)).weak().small());
+                }
+
+                // the next page loads once the bottom scrolls into view
+                if let Some(error) = &self.gifs.error {
+                    ui.label(egui::RichText::new(error).color(ui.visuals().error_fg_color));
+                    if ui.button("try again").clicked() {
+                        self.fetch_gifs();
+                    }
+                } else {
+                    let bottom = ui.horizontal(|ui| {
+                        if self.gifs.is_loading() {
+                            ui.spinner();
+                        }
+                    }).response;
+                    if ui.is_rect_visible(bottom.rect) {
+                        self.fetch_gifs();
+                    }
+                }
+            });
+    }
+
+    // favourite or recently sent gifs, shown from disk so they work offline
+    fn saved_gif_tab(&mut self, ui: &mut egui::Ui, recent: bool, width: f32, height: f32) {
+        let list = if recent { &self.saved_gifs.recent } else { &self.saved_gifs.favourites };
+        if list.is_empty() {
+            ui.label(egui::RichText::new(if recent {
+                "GIFs you send show up here"
+            } else {
+                "Star a GIF to keep it here"
+            }).weak());
+            return;
+        }
+
+        let mut action = None;
+        egui::ScrollArea::vertical()
+            .max_width(width)
+            .max_height(height)
+            .auto_shrink(false)
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for saved in list {
+                        let favourite = self.saved_gifs.is_favourite(&saved.gif);
+                        let texture = self.textures.get(ui.ctx(), &saved.preview_hash, || self.saved_gifs.preview(&saved.preview_hash));
+                        let clicked = match texture {
+                            Some(texture) => gif_tile(ui, texture, favourite),
+                            // the preview went missing from disk
+                            None => ui.button(gif::file_name(&saved.gif.id)).clicked().then_some(GifTileAction::Pick),
+                        };
+                        if let Some(clicked) = clicked {
+                            action = Some((clicked, saved.clone()));
+                        }
+                    }
+                });
             });
+
+        match action {
+            Some((GifTileAction::Pick, saved)) => self.pick_gif(saved, None),
+            Some((GifTileAction::Star, saved)) => self.saved_gifs.toggle_favourite(saved, None),
+            None => {},
+        }
+    }
+
+    // gifs go out as a reference when recipients can fetch them themselves,
+    // otherwise the full gif is downloaded and staged like any other file.
+    // either way it goes to the top of the recent tab
+    fn pick_gif(&mut self, saved: SavedGif, preview: Option<Vec<u8>>) {
+        self.saved_gifs.record_sent(saved.clone(), preview.as_deref());
+        if saved.shareable && self.settings.gifs_by_reference {
+            self.send_gif(saved.gif);
+            return;
+        }
+
+        // the provider it came from if that is still the one set up,
+        // otherwise straight from its url
+        let provider = self.gif_provider.clone().filter(|p| p.name() == saved.gif.provider);
+        let client = self.gif_media.client();
+        let tx_clone = self.network.tx.clone();
+        self.rt_handle.spawn(async move {
+            let gif = saved.gif;
+            let name = gif::file_name(&gif.id);
+            let bytes = match provider {
+                Some(provider) => {
+                    let info = GifInfo { id: gif.id, url: gif.url, preview_url: gif.preview_url };
+                    provider.download(&info, MediaSize::Full).await
+                },
+                None if gif.is_valid() => gif::media::fetch(&client, &gif.url).await,
+                None => Err(format!("the {} GIF provider isn't set up", gif.provider)),
+            };
+            let _ = tx_clone.send(gif::GifEvent::Inline { name, bytes }).await;
+        });
     }
 
-    fn fetch_featured_gifs(&mut self) {
-        let mut api_clone = self.tenor_api.clone();
+    // loads the next page of the current search, or of featured gifs
+    fn fetch_gifs(&mut self) {
+        let Some(provider) = self.gif_provider.clone() else {
+            return;
+        };
+        let Some(pos) = self.gifs.start_page() else {
+            return;
+        };
         let tx_clone = self.network.tx.clone();
+        let query = self.gifs.query.clone();
     
         self.rt_handle.spawn(async move {
-            let mut gif_results = Vec::<gif::Gif>::new();
+            let page = gif::load_page(provider, query, pos, gif::PAGE_SIZE).await;
+            let _ = tx_clone.send(gif::GifEvent::Page(page)).await;
+        });
+    }
+
+    // categories with their tile images, and trending searches
+    fn fetch_gif_extras(&mut self) {
+        let Some(provider) = self.gif_provider.clone() else {
+            return;
+        };
+        // empty while they load, so opening the popup again doesn't ask twice
+        self.io.gif_browse.categories = Some(Vec::new());
+        let tx_clone = self.network.tx.clone();
+
+        let trending_provider = provider.clone();
+        let trending_tx = tx_clone.clone();
+        self.rt_handle.spawn(async move {
+            match trending_provider.trending().await {
+                Ok(terms) => {
+                    let _ = trending_tx.send(gif::GifEvent::Trending(terms)).await;
+                },
+                Err(e) => eprintln!("couldn't load trending gif searches: {e}"),
+            }
+        });
 
-            // get api response
-            let resp = match api_clone.featured(2).await {                    
-                Ok(resp) => {resp},
+        self.rt_handle.spawn(async move {
+            let categories = match provider.categories().await {
+                Ok(categories) => categories,
                 Err(e) => {
-                    eprintln!("{}", e);
+                    eprintln!("couldn't load gif categories: {e}");
+                    let _ = tx_clone.send(gif::GifEvent::Categories(Err(e))).await;
                     return;
                 },
             };
-        
-            // create a vec of gif structs to send back
-            for i in resp {
-                let bytes = reqwest::get(i.url.clone())
-                    .await
-                    .unwrap()
-                    .bytes()
-                    .await
-                    .unwrap()
-                    .to_vec();
-        
-                gif_results.push(
-                    gif::Gif {
-                        id: i.id.clone(),
-                        url: i.url.clone(),
-                        tinygif_url: i.tinygif_url.clone(),
-                        gif_bytes: bytes,
+            let _ = tx_clone.send(gif::GifEvent::Categories(Ok(categories.clone()))).await;
+
+            // tiles show their names until their image arrives
+            for category in categories {
+                let Some(image) = category.image else {
+                    continue;
+                };
+                let provider = provider.clone();
+                let tx_clone = tx_clone.clone();
+                tokio::spawn(async move {
+                    match gif::download_preview(provider.as_ref(), image).await {
+                        Ok(gif) => {
+                            let _ = tx_clone.send(gif::GifEvent::CategoryImage { query: category.query, gif }).await;
+                        },
+                        Err(e) => eprintln!("couldn't download the image for {}: {e}", category.name),
                     }
-                );
+                });
             }
-    
-            let _ = tx_clone.try_send(gif_results);
-            
         });
     }
 
-    fn fetch_search_gifs(&mut self) {
-        let mut api_clone = self.tenor_api.clone();
+    fn fetch_gif_suggestions(&mut self, prefix: String) {
+        let Some(provider) = self.gif_provider.clone() else {
+            return;
+        };
         let tx_clone = self.network.tx.clone();
-        let search = self.io.gif_search_text.clone();
-    
+
         self.rt_handle.spawn(async move {
-    
-            // get api response
-            let resp = match api_clone.search(search, 2).await {                    
-                Ok(resp) => {resp},
-                Err(e) => {
-                    eprintln!("{}", e);
-                    return;
+            match provider.autocomplete(&prefix).await {
+                Ok(terms) => {
+                    let _ = tx_clone.send(gif::GifEvent::Suggestions { prefix, terms }).await;
                 },
-            };
-    
-            let mut gif_results = Vec::<gif::Gif>::new();
-    
-            // create a vec of gif structs to send back
-            for i in resp {
-                let bytes = reqwest::get(i.url.clone())
-                    .await
-                    .unwrap()
-                    .bytes()
-                    .await
-                    .unwrap()
-                    .to_vec();
-        
-                gif_results.push(
-                    gif::Gif {
-                        id: i.id.clone(),
-                        url: i.url.clone(),
-                        tinygif_url: i.tinygif_url.clone(),
-                        gif_bytes: bytes,
-                    }
-                );
+                Err(e) => eprintln!("couldn't load gif suggestions: {e}"),
             }
-    
-            let _ = tx_clone.try_send(gif_results);
-    
         });
     }
 
-}
\ No newline at end of file
+}
+
+enum GifTileAction {
+    Pick,
+    Star,
+}
+
+// a gif in a grid with a star to favourite it
+fn gif_tile(ui: &mut egui::Ui, texture: egui::load::SizedTexture, favourite: bool) -> Option<GifTileAction> {
+    ui.vertical(|ui| {
+        let image = egui::Image::from_texture(texture)
+            .max_width(110.0)
+            .corner_radius(5);
+        let picked = ui.add(egui::Button::image(image)).clicked();
+        let (star, hint) = if favourite { ("★", "remove from favourites") } else { ("☆", "add to favourites") };
+        let starred = ui.small_button(star).on_hover_text(hint).clicked();
+        if picked {
+            Some(GifTileAction::Pick)
+        } else {
+            starred.then_some(GifTileAction::Star)
+        }
+    })
+    .inner
+}
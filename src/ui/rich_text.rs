@@ -0,0 +1,245 @@
+use egui::{
+    Color32, FontId, Stroke, TextFormat,
+    load::SizedTexture,
+    text::LayoutJob,
+};
+
+use crate::{
+    emoji::{self, Piece},
+    markup::{Block, Span},
+};
+
+// the image of a server emoji by name, None for names the server doesn't have
+pub(crate) type CustomEmojiLookup<'a> = dyn FnMut(&egui::Context, &str) -> Option<SizedTexture> + 'a;
+
+// custom emoji are drawn a little taller than the text around them
+const CUSTOM_EMOJI_SCALE: f32 = 1.4;
+
+enum Inline<'a> {
+    Run(Vec<Span>),
+    Link(&'a Span),
+    Emoji(SizedTexture, &'a str),
+}
+
+// text format for a span, hidden spoilers are drawn as a solid bar
+fn span_format(ui: &egui::Ui, span: &Span, reveal_spoilers: bool) -> TextFormat {
+    let visuals = ui.visuals();
+    let style = span.style;
+    let mut format = TextFormat {
+        font_id: if style.code {
+            FontId::monospace(egui::TextStyle::Body.resolve(ui.style()).size)
+        } else {
+            egui::TextStyle::Body.resolve(ui.style())
+        },
+        color: if style.bold {
+            visuals.strong_text_color()
+        } else {
+            visuals.text_color()
+        },
+        italics: style.italics,
+        ..Default::default()
+    };
+
+    if style.code {
+        format.background = visuals.code_bg_color;
+    }
+    if style.strike {
+        format.strikethrough = Stroke::new(1.0, format.color);
+    }
+    if style.spoiler {
+        let bar = visuals.extreme_bg_color;
+        format.background = bar;
+        if !reveal_spoilers {
+            format.color = bar;
+        }
+    }
+    format
+}
+
+pub(crate) fn layout_spans(ui: &egui::Ui, spans: &[Span], reveal_spoilers: bool) -> LayoutJob {
+    let mut job = LayoutJob::default();
+    for span in spans {
+        job.append(&span.text, 0.0, span_format(ui, span, reveal_spoilers));
+    }
+    job
+}
+
+fn has_spoiler(spans: &[Span]) -> bool {
+    spans.iter().any(|s| s.style.spoiler)
+}
+
+fn show_run(ui: &mut egui::Ui, spans: &[Span], reveal_spoilers: bool) -> bool {
+    let job = layout_spans(ui, spans, reveal_spoilers);
+    if has_spoiler(spans) {
+        let resp = ui.add(egui::Label::new(job).sense(egui::Sense::click()));
+        resp.on_hover_cursor(egui::CursorIcon::PointingHand).clicked()
+    } else {
+        ui.label(job);
+        false
+    }
+}
+
+// splits spans into runs of text, links and custom emoji. code and spoilers
+// are left as text, links in spoilers too until they are revealed
+fn split_inline<'a>(
+    ctx: &egui::Context,
+    spans: &'a [Span],
+    reveal_spoilers: bool,
+    custom: &mut CustomEmojiLookup,
+) -> Vec<Inline<'a>> {
+    let mut pieces = Vec::new();
+    let mut run = Vec::<Span>::new();
+    for span in spans {
+        if span.link.is_some() && (reveal_spoilers || !span.style.spoiler) {
+            if !run.is_empty() {
+                pieces.push(Inline::Run(std::mem::take(&mut run)));
+            }
+            pieces.push(Inline::Link(span));
+            continue;
+        }
+        if span.style.code || span.style.spoiler {
+            run.push(span.clone());
+            continue;
+        }
+        for piece in emoji::split_custom(&span.text, |name| custom(ctx, name).is_some()) {
+            match piece {
+                Piece::Text(text) => run.push(Span { text: text.to_string(), style: span.style, link: None }),
+                Piece::Custom(name) => {
+                    let Some(texture) = custom(ctx, name) else {
+                        continue;
+                    };
+                    if !run.is_empty() {
+                        pieces.push(Inline::Run(std::mem::take(&mut run)));
+                    }
+                    pieces.push(Inline::Emoji(texture, name));
+                },
+            }
+        }
+    }
+    if !run.is_empty() {
+        pieces.push(Inline::Run(run));
+    }
+    pieces
+}
+
+// shows a paragraph, returns true when a spoiler in it was clicked
+fn show_spans(ui: &mut egui::Ui, spans: &[Span], reveal_spoilers: bool, custom: &mut CustomEmojiLookup) -> bool {
+    let pieces = split_inline(ui.ctx(), spans, reveal_spoilers, custom);
+    if let [Inline::Run(run)] = pieces.as_slice() {
+        return show_run(ui, run, reveal_spoilers);
+    }
+
+    // links and images can't be part of a layout job, so text runs, links
+    // and custom emoji are laid out one after another
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        let mut clicked = false;
+        for piece in pieces {
+            match piece {
+                Inline::Link(span) => {
+                    let url = span.link.as_deref().unwrap_or_default();
+                    let text = egui::RichText::new(&span.text);
+                    ui.hyperlink_to(text, url).on_hover_text(url);
+                }
+                Inline::Emoji(texture, name) => {
+                    let size = ui.text_style_height(&egui::TextStyle::Body) * CUSTOM_EMOJI_SCALE;
+                    ui.add(egui::Image::from_texture(texture).fit_to_exact_size(egui::vec2(size, size)))
+                        .on_hover_text(format!(":{name}:"));
+                }
+                Inline::Run(run) => clicked |= show_run(ui, &run, reveal_spoilers),
+            }
+        }
+        clicked
+    })
+    .inner
+}
+
+// syntect looks languages up by name or file extension, map the usual
+// fence tags onto an extension it knows
+fn syntax_token(lang: &str) -> &str {
+    match lang.to_ascii_lowercase().as_str() {
+        "rust" => "rs",
+        "python" => "py",
+        "javascript" => "js",
+        "typescript" => "ts",
+        "shell" | "bash" | "zsh" => "sh",
+        "c++" | "cpp" => "cpp",
+        "csharp" | "c#" => "cs",
+        "markdown" => "md",
+        "ruby" => "rb",
+        "yaml" => "yml",
+        _ => lang,
+    }
+}
+
+fn show_code(ui: &mut egui::Ui, lang: Option<&str>, code: &str) {
+    let theme = egui_extras::syntax_highlighting::CodeTheme::from_memory(ui.ctx(), ui.style());
+    let job = egui_extras::syntax_highlighting::highlight(
+        ui.ctx(),
+        ui.style(),
+        &theme,
+        code,
+        syntax_token(lang.unwrap_or_default()),
+    );
+
+    egui::Frame::new()
+        .fill(ui.visuals().code_bg_color)
+        .corner_radius(4)
+        .inner_margin(6)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if let Some(lang) = lang {
+                    ui.label(egui::RichText::new(lang).weak().small());
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("copy").clicked() {
+                        ui.ctx().copy_text(code.to_string());
+                    }
+                });
+            });
+
+            egui::ScrollArea::horizontal()
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    ui.add(egui::Label::new(job).wrap_mode(egui::TextWrapMode::Extend));
+                });
+        });
+}
+
+// renders parsed markup blocks, returns true when a spoiler was clicked.
+// id_salt tells apart the code blocks of different messages
+pub(crate) fn show_blocks(
+    ui: &mut egui::Ui,
+    id_salt: &str,
+    blocks: &[Block],
+    reveal_spoilers: bool,
+    custom: &mut CustomEmojiLookup,
+) -> bool {
+    let mut spoiler_clicked = false;
+
+    for (i, block) in blocks.iter().enumerate() {
+        match block {
+            Block::Paragraph(spans) => {
+                spoiler_clicked |= show_spans(ui, spans, reveal_spoilers, custom);
+            }
+            Block::Quote(spans) => {
+                let inner = ui.horizontal(|ui| {
+                    ui.add_space(8.0);
+                    show_spans(ui, spans, reveal_spoilers, custom)
+                });
+                let rect = inner.response.rect;
+                ui.painter().vline(
+                    rect.left() + 2.0,
+                    rect.y_range(),
+                    Stroke::new(3.0, Color32::from_gray(110)),
+                );
+                spoiler_clicked |= inner.inner;
+            }
+            Block::Code { lang, code } => {
+                // each block needs its own id for the scroll area
+                ui.push_id((id_salt, i), |ui| show_code(ui, lang.as_deref(), code));
+            }
+        }
+    }
+    spoiler_clicked
+}
@@ -0,0 +1,224 @@
+// decoded images uploaded as textures, keyed by the content hash of their
+// bytes so each image is decoded once however many times it is shown. the
+// least recently used textures are dropped when the memory budget runs out
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    time::Duration,
+};
+
+use egui::{ColorImage, TextureHandle, TextureOptions, load::SizedTexture};
+use image::{AnimationDecoder, ImageFormat, codecs::gif::GifDecoder};
+
+use crate::attachment::validate;
+
+// roughly how much decoded pixel data to keep around
+pub const TEXTURE_BUDGET_BYTES: usize = 256 * 1024 * 1024;
+// browsers treat shorter gif frame delays as this
+const MIN_FRAME_SECS: f64 = 0.02;
+
+struct Frame {
+    texture: TextureHandle,
+    // how long the frame stays up, zero for still images
+    delay: f64,
+}
+
+struct Entry {
+    frames: Vec<Frame>,
+    bytes: usize,
+    last_used: u64,
+}
+
+impl Entry {
+    // the frame to show at `time` and how long until the next one
+    fn frame_at(&self, time: f64) -> (&Frame, Option<f64>) {
+        let duration: f64 = self.frames.iter().map(|f| f.delay).sum();
+        if self.frames.len() < 2 || duration <= 0.0 {
+            return (&self.frames[0], None);
+        }
+
+        let mut t = time % duration;
+        for frame in &self.frames {
+            if t < frame.delay {
+                return (frame, Some(frame.delay - t));
+            }
+            t -= frame.delay;
+        }
+        (&self.frames[0], None)
+    }
+}
+
+pub(crate) struct TextureCache {
+    entries: HashMap<String, Entry>,
+    // images that failed to decode, so they aren't tried every frame
+    failed: HashSet<String>,
+    budget: usize,
+    used: usize,
+    clock: u64,
+}
+
+impl Default for TextureCache {
+    fn default() -> Self {
+        Self::new(TEXTURE_BUDGET_BYTES)
+    }
+}
+
+impl TextureCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            failed: HashSet::new(),
+            budget,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    // the texture to draw for an image, decoding it from `bytes` the first
+    // time. animated gifs return the current frame and schedule a repaint
+    pub fn get<B: AsRef<[u8]>>(
+        &mut self,
+        ctx: &egui::Context,
+        hash: &str,
+        bytes: impl FnOnce() -> Option<B>,
+    ) -> Option<SizedTexture> {
+        self.clock += 1;
+
+        if !self.entries.contains_key(hash) {
+            if self.failed.contains(hash) {
+                return None;
+            }
+            let bytes = bytes()?;
+            let Some(frames) = decode(ctx, hash, bytes.as_ref()) else {
+                eprintln!("could not decode image {hash}");
+                self.failed.insert(hash.to_string());
+                return None;
+            };
+            let size = frames.iter().map(|f| f.texture.byte_size()).sum();
+            self.used += size;
+            self.entries.insert(
+                hash.to_string(),
+                Entry { frames, bytes: size, last_used: self.clock },
+            );
+            self.evict(hash);
+        }
+
+        let entry = self.entries.get_mut(hash)?;
+        entry.last_used = self.clock;
+        let (frame, next) = entry.frame_at(ctx.input(|i| i.time));
+        if let Some(next) = next {
+            ctx.request_repaint_after(Duration::from_secs_f64(next));
+        }
+        Some(SizedTexture::from_handle(&frame.texture))
+    }
+
+    // drops least recently used textures until the cache fits its budget,
+    // never the one just added
+    fn evict(&mut self, keep: &str) {
+        while self.used > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(hash, _)| hash.as_str() != keep)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| hash.clone());
+            let Some(oldest) = oldest else {
+                return;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used -= entry.bytes;
+            }
+        }
+    }
+
+}
+
+// images from the network are checked before decoding, long animations are
+// cut short rather than filling memory
+fn decode(ctx: &egui::Context, hash: &str, bytes: &[u8]) -> Option<Vec<Frame>> {
+    let info = validate::check_image(bytes).ok()?;
+    let frame_pixels = info.width as u64 * info.height as u64;
+
+    let images: Vec<(ColorImage, f64)> = if info.format == ImageFormat::Gif {
+        let max_frames = (validate::MAX_ANIMATION_PIXELS / frame_pixels).max(1) as usize;
+        let mut images = Vec::new();
+        for frame in GifDecoder::new(Cursor::new(bytes)).ok()?.into_frames().take(max_frames) {
+            let Ok(frame) = frame else {
+                break;
+            };
+            let delay = Duration::from(frame.delay()).as_secs_f64().max(MIN_FRAME_SECS);
+            let buffer = frame.into_buffer();
+            let size = [buffer.width() as usize, buffer.height() as usize];
+            images.push((ColorImage::from_rgba_unmultiplied(size, buffer.as_raw()), delay));
+        }
+        images
+    } else {
+        let buffer = validate::limited_reader(bytes, info.format, validate::NETWORK_LIMITS).decode().ok()?.to_rgba8();
+        let size = [buffer.width() as usize, buffer.height() as usize];
+        vec![(ColorImage::from_rgba_unmultiplied(size, buffer.as_raw()), 0.0)]
+    };
+    if images.is_empty() {
+        return None;
+    }
+
+    Some(
+        images
+            .into_iter()
+            .enumerate()
+            .map(|(i, (image, delay))| Frame {
+                texture: ctx.load_texture(format!("{hash}#{i}"), image, TextureOptions::LINEAR),
+                delay,
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::encode_image;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        encode_image(width, height, ImageFormat::Png)
+    }
+
+    #[test]
+    fn images_are_decoded_once() {
+        let ctx = egui::Context::default();
+        let mut cache = TextureCache::default();
+        let bytes = png(4, 4);
+
+        assert!(cache.get(&ctx, "a", || Some(&bytes)).is_some());
+        // a cached image doesn't need its bytes again
+        let texture = cache.get(&ctx, "a", || None::<Vec<u8>>).unwrap();
+        assert_eq!(texture.size, egui::vec2(4.0, 4.0));
+        assert_eq!(cache.used, 4 * 4 * 4);
+    }
+
+    #[test]
+    fn least_recently_used_images_are_evicted() {
+        let ctx = egui::Context::default();
+        // room for two 8x8 images
+        let mut cache = TextureCache::new(2 * 8 * 8 * 4);
+        let bytes = png(8, 8);
+
+        cache.get(&ctx, "a", || Some(&bytes));
+        cache.get(&ctx, "b", || Some(&bytes));
+        cache.get(&ctx, "a", || Some(&bytes));
+        cache.get(&ctx, "c", || Some(&bytes));
+
+        assert!(cache.entries.contains_key("a"));
+        assert!(!cache.entries.contains_key("b"));
+        assert!(cache.entries.contains_key("c"));
+        assert_eq!(cache.used, 2 * 8 * 8 * 4);
+    }
+
+    #[test]
+    fn undecodable_images_are_not_retried() {
+        let ctx = egui::Context::default();
+        let mut cache = TextureCache::default();
+
+        assert!(cache.get(&ctx, "junk", || Some(b"not an image")).is_none());
+        assert!(cache.get(&ctx, "junk", || -> Option<Vec<u8>> { panic!("decoded twice") }).is_none());
+    }
+}
@@ -1,2 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod user;
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod presence;
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+// how long the window can go without input before the user is marked away
+pub const AWAY_AFTER_SECS: f64 = 5.0 * 60.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    DoNotDisturb,
+    Invisible,
+}
+
+impl Presence {
+    pub const ALL: [Presence; 4] = [
+        Presence::Online,
+        Presence::Away,
+        Presence::DoNotDisturb,
+        Presence::Invisible,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Presence::Online => "online",
+            Presence::Away => "away",
+            Presence::DoNotDisturb => "do not disturb",
+            Presence::Invisible => "invisible",
+        }
+    }
+
+    pub fn color(&self) -> egui::Color32 {
+        match self {
+            Presence::Online => egui::Color32::from_rgb(67, 181, 129),
+            Presence::Away => egui::Color32::from_rgb(250, 166, 26),
+            Presence::DoNotDisturb => egui::Color32::from_rgb(240, 71, 71),
+            Presence::Invisible => egui::Color32::GRAY,
+        }
+    }
+}
+
+// presence plus the optional custom text a user shows next to their name
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UserStatus {
+    pub presence: Presence,
+    pub text: String,
+}
+
+// the user list as other users see it, invisible users appear offline
+pub fn visible(roster: &HashMap<String, UserStatus>) -> HashMap<String, UserStatus> {
+    roster
+        .iter()
+        .filter(|(_, status)| status.presence != Presence::Invisible)
+        .map(|(name, status)| (name.clone(), status.clone()))
+        .collect()
+}
+
+// draws the coloured dot used in the user list and status selector
+pub fn status_dot(ui: &mut egui::Ui, presence: Presence) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(10.0, 10.0), egui::Sense::hover());
+    let painter = ui.painter();
+    if presence == Presence::Invisible {
+        painter.circle_stroke(rect.center(), 4.0, egui::Stroke::new(1.5, presence.color()));
+    } else {
+        painter.circle_filled(rect.center(), 4.0, presence.color());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Connect;
+
+    fn status(presence: Presence) -> UserStatus {
+        UserStatus { presence, text: String::new() }
+    }
+
+    #[test]
+    fn invisible_users_are_left_out_of_the_user_list() {
+        let roster = HashMap::from([
+            ("ann".to_string(), status(Presence::Online)),
+            ("bob".to_string(), status(Presence::Invisible)),
+            ("cat".to_string(), UserStatus { presence: Presence::DoNotDisturb, text: "busy".to_string() }),
+        ]);
+        let shown = visible(&roster);
+        assert_eq!(shown.len(), 2);
+        assert_eq!(shown["ann"], status(Presence::Online));
+        assert_eq!(shown["cat"].text, "busy");
+        assert!(!shown.contains_key("bob"));
+    }
+
+    #[test]
+    fn joins_are_relayed_without_the_status_or_token() {
+        let connect = |presence| Connect {
+            user_name: "ann".to_string(),
+            status: Some(status(presence)),
+            admin_token: Some("secret".to_string()),
+        };
+        for presence in [Presence::Online, Presence::Away, Presence::DoNotDisturb] {
+            let relayed = connect(presence).relayed().unwrap();
+            assert_eq!(relayed.user_name, "ann");
+            assert_eq!(relayed.status, None);
+            assert_eq!(relayed.admin_token, None);
+        }
+        assert!(connect(Presence::Invisible).relayed().is_none());
+    }
+}
@@ -1,10 +1,76 @@
-use std::collections::HashMap;
-use crate::user::user::User;
+use std::{collections::HashMap, sync::Arc};
+use crate::user::{presence::{AWAY_AFTER_SECS, Presence, UserStatus}, user::User};
+
+// a picture offered on the start screen
+pub(crate) struct ProfilePicture {
+    pub(crate) hash: String,
+    pub(crate) bytes: Arc<[u8]>,
+}
 
 // handles user related content like the local user, other connected users
 #[derive(Default)]
 pub(crate) struct UserState {
     pub(crate) local: User,
-    pub(crate) peers: HashMap<String, String>,
-    pub(crate) profile_picture_list: Vec<String>,
+    pub(crate) peers: HashMap<String, UserStatus>,
+    pub(crate) profile_picture_list: Vec<ProfilePicture>,
+    // everyone's avatars by content hash, as sent by the server
+    pub(crate) avatars: HashMap<String, Arc<[u8]>>,
+    // input time of the last user activity, used for automatic away
+    pub(crate) last_activity: f64,
+    // set when the away status was applied automatically rather than chosen
+    pub(crate) auto_away: bool,
+}
+
+impl UserState {
+    // marks the user away after a period of inactivity and back online on
+    // input, true when the status changed and the server should be told
+    pub fn track_activity(&mut self, now: f64, active: bool) -> bool {
+        if active {
+            self.last_activity = now;
+            if self.auto_away {
+                self.auto_away = false;
+                self.local.status.presence = Presence::Online;
+                return true;
+            }
+        } else if self.local.status.presence == Presence::Online && now - self.last_activity > AWAY_AFTER_SECS {
+            self.auto_away = true;
+            self.local.status.presence = Presence::Away;
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_users_go_away_and_come_back_on_input() {
+        let mut user = UserState::default();
+        assert!(!user.track_activity(AWAY_AFTER_SECS, false));
+        assert_eq!(user.local.status.presence, Presence::Online);
+
+        assert!(user.track_activity(AWAY_AFTER_SECS + 1.0, false));
+        assert_eq!(user.local.status.presence, Presence::Away);
+        assert!(user.auto_away);
+        // already away, nothing more to send
+        assert!(!user.track_activity(AWAY_AFTER_SECS + 60.0, false));
+
+        assert!(user.track_activity(AWAY_AFTER_SECS + 61.0, true));
+        assert_eq!(user.local.status.presence, Presence::Online);
+        assert!(!user.auto_away);
+        assert_eq!(user.last_activity, AWAY_AFTER_SECS + 61.0);
+    }
+
+    #[test]
+    fn chosen_statuses_are_left_alone() {
+        for presence in [Presence::Away, Presence::DoNotDisturb, Presence::Invisible] {
+            let mut user = UserState::default();
+            user.local.status.presence = presence;
+            assert!(!user.track_activity(AWAY_AFTER_SECS * 2.0, false));
+            assert!(!user.track_activity(AWAY_AFTER_SECS * 2.0 + 1.0, true));
+            assert_eq!(user.local.status.presence, presence);
+        }
+    }
 }
@@ -1,7 +1,19 @@
+use crate::{hash::content_hash, user::presence::UserStatus};
+
 #[derive(Default)]
 pub struct User {
     pub(crate) name: String,
     pub(crate) picture: Vec::<u8>,
+    // content hash of the picture, messages refer to the avatar by it
+    pub(crate) picture_hash: String,
+    pub(crate) status: UserStatus,
+}
+
+impl User {
+    pub fn set_picture(&mut self, picture: Vec<u8>) {
+        self.picture_hash = content_hash(&picture);
+        self.picture = picture;
+    }
 }
 
 // impl User {
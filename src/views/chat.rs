@@ -1,16 +1,29 @@
-use crate::App;
+use crate::{
+    App,
+    emoji,
+    hash::content_hash,
+    mention::MentionEntry,
+    message::{CustomEmoji, MessageType},
+};
+use std::time::Duration;
 
 impl App {
     // rendering the chat state along with its UI components
     pub fn render_chat(&mut self, ctx: &egui::Context) {
         
-        // recieve message network side
-        if let Some(net) = &mut self.network.client {
-            match net.recv() {
-                Some(msg) => self.io.messages.push(msg),
-                None => {},
-            }
+        // recieve messages network side
+        while let Some(msg) = self.network.client.as_mut().and_then(|net| net.recv()) {
+            self.handle_incoming(ctx, msg);
+        }
+        self.link_previews.poll();
+        self.gif_media.poll();
+        if let Some(net) = self.network.client.as_ref().filter(|net| net.is_connected()) {
+            self.transfers.pump(net);
         }
+        self.update_saves(ctx);
+        self.update_emoji_upload(ctx);
+
+        self.update_away(ctx);
 
         // render ui
         self.message_panel(ctx);
@@ -18,5 +31,127 @@ impl App {
         self.side_panel(ctx);
 
         self.chat_panel(ctx);
+
+        self.lightbox(ctx);
+    }
+
+    fn handle_incoming(&mut self, ctx: &egui::Context, msg: MessageType) {
+        if let MessageType::Message(m) = &msg
+            && m.user_name != self.user.local.name
+            && m.mentions_user(&self.user.local.name)
+        {
+            self.io.mentions.push(MentionEntry {
+                room: m.room.clone(),
+                user_name: m.user_name.clone(),
+                message: m.message.clone(),
+                timestamp: m.timestamp.clone(),
+            });
+        }
+
+        match msg {
+            MessageType::LinkPreview(preview) => self.link_previews.insert(preview),
+            // kept by hash, every message refers to its sender's avatar that way
+            MessageType::Avatar(avatar) => {
+                self.user.avatars.insert(avatar.hash, avatar.bytes.into());
+            },
+            MessageType::CustomEmojiList(list) => self.server_emoji.replace(list),
+            MessageType::CustomEmoji(custom) => self.server_emoji.insert(custom),
+            MessageType::StickerPacks(packs) => self.stickers.server = packs,
+            MessageType::BlobChunk(chunk) => self.attachments.receive_chunk(chunk),
+            MessageType::BlobMissing(hash) => {
+                self.attachments.missing(hash, ctx.input(|i| i.time));
+            },
+            MessageType::UploadAck(ack) => self.transfers.ack(ack),
+            MessageType::UploadRejected(rejected) => {
+                let name = self.transfers.reject(&rejected.hash).unwrap_or_else(|| "an attachment".to_string());
+                self.notify(format!("{name} was not sent: {}", rejected.reason));
+            },
+            msg => self.io.messages.push(msg),
+        }
+    }
+
+    // saves attachments to the paths picked in the save dialog, downloading
+    // them first when needed
+    fn update_saves(&mut self, ctx: &egui::Context) {
+        self.io.save_dialog.update(ctx);
+        if let Some(path) = self.io.save_dialog.take_picked()
+            && let Some(descriptor) = self.io.saving.take()
+        {
+            self.attachments.request_again(&descriptor, self.network.client.as_ref());
+            self.io.pending_saves.push((path, descriptor));
+        }
+
+        // saves are dropped once their download can't finish
+        for (path, descriptor) in std::mem::take(&mut self.io.pending_saves) {
+            if let Some(bytes) = self.attachments.get(&descriptor.hash) {
+                match std::fs::write(&path, &bytes) {
+                    Ok(_) => self.notify(format!("saved {} to {}", descriptor.name, path.display())),
+                    Err(e) => self.notify(format!("could not save {}: {e}", descriptor.name)),
+                }
+            } else if let Some(reason) = self.attachments.failure(&descriptor.hash) {
+                self.notify(format!("could not save {}: {reason}", descriptor.name));
+            } else {
+                self.io.pending_saves.push((path, descriptor));
+            }
+        }
+    }
+
+    // sends the image picked in the emoji dialog to the server as a custom
+    // emoji, named after the file when no name was typed
+    fn update_emoji_upload(&mut self, ctx: &egui::Context) {
+        self.io.emoji_dialog.update(ctx);
+        let Some(path) = self.io.emoji_dialog.take_picked() else {
+            return;
+        };
+
+        let typed = self.io.emoji_picker.new_name.trim().trim_matches(':');
+        let name = if typed.is_empty() {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            stem.to_lowercase().replace([' ', '-', '.'], "_")
+        } else {
+            typed.to_string()
+        };
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.notify(format!("could not read {}: {e}", path.display()));
+                return;
+            },
+        };
+        let custom = CustomEmoji { name, hash: content_hash(&bytes), bytes };
+        // checked here too so mistakes show up without a round trip
+        if let Err(reason) = emoji::check_custom(&custom) {
+            self.notify(format!(":{}: was not added: {reason}", custom.name));
+            return;
+        }
+
+        self.io.emoji_picker.new_name.clear();
+        if let Some(net) = &self.network.client {
+            net.send(MessageType::CustomEmoji(custom));
+        }
+    }
+
+    // marks the user away after a period of inactivity and back online on input
+    fn update_away(&mut self, ctx: &egui::Context) {
+        let (now, active) = ctx.input(|i| {
+            let active = i.events.iter().any(|e| {
+                matches!(
+                    e,
+                    egui::Event::Key { .. }
+                        | egui::Event::Text(_)
+                        | egui::Event::PointerMoved(_)
+                        | egui::Event::PointerButton { .. }
+                        | egui::Event::MouseWheel { .. }
+                )
+            });
+            (i.time, active)
+        });
+
+        if self.user.track_activity(now, active) {
+            self.send_status();
+        }
+
+        // keep updating while idle so the away timer can fire
+        ctx.request_repaint_after(Duration::from_secs(30));
     }
-}
\ No newline at end of file
+}
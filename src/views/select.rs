@@ -1,5 +1,4 @@
 use crate::{App, views::state::View};
-use egui;
 
 impl App {
     pub fn render_select(&mut self, ctx: &egui::Context) {
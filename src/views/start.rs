@@ -1,4 +1,3 @@
-use std::fs::read;
 use crate::App::{self};
 use crate::views::state::View;
 use egui::{RichText, vec2};
@@ -22,13 +21,15 @@ impl App {
 
                 ui.heading(RichText::new("Choose a Profile Picture"));
                 egui::Grid::new("profile_pictures").show(ui, |ui| {
-                    for path in self.user.profile_picture_list.iter() {
-                        let image = egui::Image::from_uri(path);
+                    for picture in self.user.profile_picture_list.iter() {
+                        let Some(texture) = self.textures.get(ctx, &picture.hash, || Some(&picture.bytes)) else {
+                            continue;
+                        };
+                        let image = egui::Image::from_texture(texture);
                         if ui.add(
                             egui::Button::image(image.fit_to_fraction(vec2(2.0, 2.0)))
                         ).clicked() {
-                            let p = path.trim_start_matches("file://");
-                            self.user.local.picture = read(p).unwrap(); // should never fail
+                            self.user.local.set_picture(picture.bytes.to_vec());
                         }
                     }
                 });